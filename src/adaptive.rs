@@ -0,0 +1,175 @@
+use crate::{
+    checkpoint::{read_f64, read_u64, write_f64, write_u64},
+    utils::{Color, PI},
+};
+use std::{
+    io::{self, Read, Write},
+    path::PathBuf,
+};
+
+/// Stops sampling a pixel once its estimated error is small enough.
+///
+/// Every pixel gets at least `min_samples`, never fewer than
+/// `MIN_SAMPLES`, and at most `max_samples`. The error is the standard
+/// error of the pixel's mean luminance, taken through a square root so dark
+/// pixels aren't held to a stricter standard than bright ones. That's only a
+/// rough stand-in for the tone mapping and sRGB curve the output goes
+/// through.
+#[derive(Debug, Clone)]
+pub struct AdaptiveSampling {
+    min_samples: u32,
+    max_samples: u32,
+    threshold: f64,
+    /// Where to write an image of how many samples each pixel ended up with.
+    heatmap: Option<PathBuf>,
+}
+
+impl AdaptiveSampling {
+    /// Fewer than this and a pixel that only sees a small bright light now
+    /// and then can look converged to black after its first few samples.
+    pub const MIN_SAMPLES: u32 = 16;
+
+    /// Both bounds are raised to at least `MIN_SAMPLES`, so asking for
+    /// fewer still takes that many samples per pixel.
+    pub fn new(min_samples: u32, max_samples: u32, threshold: f64) -> AdaptiveSampling {
+        let min_samples = min_samples.max(Self::MIN_SAMPLES);
+        Self {
+            min_samples,
+            max_samples: max_samples.max(min_samples),
+            threshold,
+            heatmap: None,
+        }
+    }
+
+    pub fn with_heatmap(mut self, path: impl Into<PathBuf>) -> Self {
+        self.heatmap = Some(path.into());
+        self
+    }
+
+    pub fn min_samples(&self) -> u32 {
+        self.min_samples
+    }
+
+    pub fn max_samples(&self) -> u32 {
+        self.max_samples
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    pub fn heatmap(&self) -> Option<&PathBuf> {
+        self.heatmap.as_ref()
+    }
+
+    pub fn converged(&self, stats: &PixelStats) -> bool {
+        stats.count() >= self.min_samples && stats.error() < self.threshold
+    }
+
+    /// Blue for pixels that stopped at `min_samples`, through green, to red
+    /// for ones that needed all of `max_samples`.
+    pub fn heat(&self, samples: u32) -> Color<f64> {
+        let range = self.max_samples.saturating_sub(self.min_samples).max(1) as f64;
+        let t = (samples.saturating_sub(self.min_samples) as f64 / range).clamp(0.0, 1.0);
+        Color::new(
+            (PI * (t - 0.5)).sin().max(0.0),
+            (PI * t).sin(),
+            (PI * (t + 0.5)).sin().max(0.0),
+        )
+    }
+}
+
+/// Running mean and variance of a pixel's sample luminance (Welford).
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PixelStats {
+    count: u32,
+    mean: f64,
+    m2: f64,
+}
+
+impl PixelStats {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        self.m2 / (self.count - 1) as f64
+    }
+
+    pub fn write_state(&self, out: &mut impl Write) -> io::Result<()> {
+        write_u64(out, self.count as u64)?;
+        write_f64(out, self.mean)?;
+        write_f64(out, self.m2)
+    }
+
+    pub fn read_state(input: &mut impl Read) -> io::Result<PixelStats> {
+        Ok(Self {
+            count: read_u64(input)? as u32,
+            mean: read_f64(input)?,
+            m2: read_f64(input)?,
+        })
+    }
+
+    /// Standard error of the mean, pushed through the sqrt display curve:
+    /// d(sqrt(x)) = dx / (2 sqrt(x)).
+    pub fn error(&self) -> f64 {
+        let standard_error = (self.variance() / self.count as f64).sqrt();
+        standard_error / (2.0 * self.mean.max(1e-4).sqrt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_match_the_two_pass_formulas() {
+        let values = [0.5, 1.5, 0.25, 2.0, 0.75];
+        let mut stats = PixelStats::default();
+        values.iter().for_each(|&v| stats.add(v));
+
+        let mean = values.iter().sum::<f64>() / 5.0;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / 4.0;
+        assert_eq!(stats.count(), 5);
+        assert!((stats.mean() - mean).abs() < 1e-12);
+        assert!((stats.variance() - variance).abs() < 1e-12);
+    }
+
+    #[test]
+    fn black_pixels_still_take_the_minimum() {
+        let adaptive = AdaptiveSampling::new(2, 64, 0.01);
+        let mut stats = PixelStats::default();
+        for _ in 1..AdaptiveSampling::MIN_SAMPLES {
+            stats.add(0.0);
+            assert!(!adaptive.converged(&stats));
+        }
+        stats.add(0.0);
+        assert!(adaptive.converged(&stats));
+    }
+
+    #[test]
+    fn heat_runs_from_blue_to_red() {
+        let adaptive = AdaptiveSampling::new(16, 64, 0.01);
+        let cold = adaptive.heat(16);
+        let hot = adaptive.heat(64);
+        assert!(cold.z > 0.99 && cold.x < 1e-9);
+        assert!(hot.x > 0.99 && hot.z < 1e-9);
+        // nothing to adapt between, but still a color
+        let flat = AdaptiveSampling::new(16, 16, 0.01);
+        assert!(flat.heat(0).z > 0.99);
+    }
+}
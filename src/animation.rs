@@ -0,0 +1,271 @@
+use crate::utils::{Point, PI};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+/// Where the camera is and what it's doing at `frame`.
+#[derive(Debug, Copy, Clone)]
+pub struct Keyframe {
+    pub frame: f64,
+    pub lookfrom: Point<f64>,
+    pub lookat: Point<f64>,
+    /// Vertical field of view in degrees.
+    pub vfov: f64,
+    pub focus_dist: f64,
+}
+
+impl Keyframe {
+    pub fn new(
+        frame: f64,
+        lookfrom: Point<f64>,
+        lookat: Point<f64>,
+        vfov: f64,
+        focus_dist: f64,
+    ) -> Keyframe {
+        Self {
+            frame,
+            lookfrom,
+            lookat,
+            vfov,
+            focus_dist,
+        }
+    }
+
+    fn to_array(self) -> [f64; 8] {
+        [
+            self.lookfrom.x,
+            self.lookfrom.y,
+            self.lookfrom.z,
+            self.lookat.x,
+            self.lookat.y,
+            self.lookat.z,
+            self.vfov,
+            self.focus_dist,
+        ]
+    }
+
+    fn from_array(frame: f64, values: [f64; 8]) -> Keyframe {
+        Self::new(
+            frame,
+            Point::new(values[0], values[1], values[2]),
+            Point::new(values[3], values[4], values[5]),
+            values[6],
+            values[7],
+        )
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Straight lines between keyframes, with sudden changes of speed at
+    /// each one.
+    Linear,
+    /// Catmull-Rom spline through the keyframes, smooth all the way.
+    #[default]
+    Spline,
+}
+
+/// Keyframes for the camera, interpolated in between. Before the first and
+/// after the last keyframe the camera holds still.
+#[derive(Debug, Clone, Default)]
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+    interpolation: Interpolation,
+}
+
+impl CameraPath {
+    pub fn new(interpolation: Interpolation) -> CameraPath {
+        Self {
+            keyframes: vec![],
+            interpolation,
+        }
+    }
+
+    /// A turntable: one full circle around `lookat` between `first` and
+    /// `last`, starting at `lookfrom` and keeping its height.
+    pub fn orbit(
+        lookfrom: Point<f64>,
+        lookat: Point<f64>,
+        vfov: f64,
+        (first, last): (f64, f64),
+    ) -> CameraPath {
+        // enough keyframes that the spline is as good as a circle
+        const STEPS: usize = 32;
+        let offset = lookfrom - lookat;
+        let focus_dist = offset.length();
+        let mut path = Self::new(Interpolation::Spline);
+        for step in 0..=STEPS {
+            let t = step as f64 / STEPS as f64;
+            let (sin, cos) = (2.0 * PI * t).sin_cos();
+            let rotated = Point::new(
+                offset.x * cos + offset.z * sin,
+                offset.y,
+                offset.z * cos - offset.x * sin,
+            );
+            let frame = first + (last - first) * t;
+            path = path.with_keyframe(Keyframe::new(
+                frame,
+                lookat + rotated,
+                lookat,
+                vfov,
+                focus_dist,
+            ));
+        }
+        path
+    }
+
+    /// Reads keyframes from text, one per line as
+    /// `frame lookfrom_x lookfrom_y lookfrom_z lookat_x lookat_y lookat_z vfov
+    /// focus_dist`. Blank lines and anything after a `#` are skipped, and a
+    /// line with just `linear` or `spline` sets the interpolation, spline
+    /// unless told otherwise.
+    pub fn read(input: impl BufRead) -> io::Result<CameraPath> {
+        let mut path = Self::new(Interpolation::Spline);
+        for (number, line) in input.lines().enumerate() {
+            let line = line?;
+            let invalid = |message: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("camera path line {}: {message}", number + 1),
+                )
+            };
+            let line = line.split('#').next().unwrap_or_default().trim();
+            match line {
+                "" => continue,
+                "linear" => path.interpolation = Interpolation::Linear,
+                "spline" => path.interpolation = Interpolation::Spline,
+                _ => {
+                    let values = line
+                        .split_whitespace()
+                        .map(|value| value.parse::<f64>().map_err(|_| invalid("not a number")))
+                        .collect::<io::Result<Vec<_>>>()?;
+                    let [frame, rest @ ..]: [f64; 9] = values
+                        .try_into()
+                        .map_err(|_| invalid("a keyframe wants 9 numbers"))?;
+                    path = path.with_keyframe(Keyframe::from_array(frame, rest));
+                }
+            }
+        }
+
+        if path.keyframes.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "camera path has no keyframes",
+            ));
+        }
+        Ok(path)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<CameraPath> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn with_keyframe(mut self, keyframe: Keyframe) -> Self {
+        let index = self
+            .keyframes
+            .partition_point(|k| k.frame <= keyframe.frame);
+        self.keyframes.insert(index, keyframe);
+        self
+    }
+
+    /// The camera at `frame`, which doesn't have to be a whole number. `None`
+    /// without any keyframes.
+    pub fn at(&self, frame: f64) -> Option<Keyframe> {
+        let keys = &self.keyframes;
+        let first = keys.first()?;
+        let last = keys.last()?;
+        if frame <= first.frame {
+            return Some(Keyframe { frame, ..*first });
+        }
+        if frame >= last.frame {
+            return Some(Keyframe { frame, ..*last });
+        }
+
+        // the segment from keys[i] to keys[i + 1]
+        let i = keys.partition_point(|k| k.frame <= frame) - 1;
+        let (k1, k2) = (keys[i], keys[i + 1]);
+        let span = k2.frame - k1.frame;
+        let t = if span > 0.0 {
+            (frame - k1.frame) / span
+        } else {
+            0.0
+        };
+        let (p1, p2) = (k1.to_array(), k2.to_array());
+
+        let values = match self.interpolation {
+            Interpolation::Linear => std::array::from_fn(|c| p1[c] + (p2[c] - p1[c]) * t),
+            Interpolation::Spline => {
+                // the ends get a neighbour that makes the tangent point
+                // straight at the next keyframe
+                let k0 = if i > 0 { keys[i - 1] } else { k1 };
+                let k3 = keys.get(i + 2).copied().unwrap_or(k2);
+                let (p0, p3) = (k0.to_array(), k3.to_array());
+
+                // tangents scaled to the segment for uneven keyframe spacing
+                let scale1 = span / (k2.frame - k0.frame);
+                let scale2 = span / (k3.frame - k1.frame);
+                let (t2, t3) = (t * t, t * t * t);
+                std::array::from_fn(|c| {
+                    let m1 = (p2[c] - p0[c]) * scale1;
+                    let m2 = (p3[c] - p1[c]) * scale2;
+                    (2.0 * t3 - 3.0 * t2 + 1.0) * p1[c]
+                        + (t3 - 2.0 * t2 + t) * m1
+                        + (-2.0 * t3 + 3.0 * t2) * p2[c]
+                        + (t3 - t2) * m2
+                })
+            }
+        };
+        Some(Keyframe::from_array(frame, values))
+    }
+}
+
+/// Puts `frame` into `pattern`, replacing the first run of `#` with the
+/// frame number padded to its length, like `frame_####.ppm` becoming
+/// `frame_0012.ppm`. Without any `#` the number goes before the extension.
+pub fn frame_path(pattern: impl AsRef<Path>, frame: u32) -> PathBuf {
+    let pattern = pattern.as_ref().to_string_lossy();
+    if let Some(start) = pattern.find('#') {
+        let width = pattern[start..].chars().take_while(|&c| c == '#').count();
+        let end = start + width;
+        return format!("{}{frame:0width$}{}", &pattern[..start], &pattern[end..]).into();
+    }
+
+    let path = Path::new(pattern.as_ref());
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}_{frame:04}.{}", extension.to_string_lossy()),
+        None => format!("{stem}_{frame:04}"),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyframes_read_from_text() {
+        let text = "# frame, lookfrom, lookat, vfov, focus\nlinear\n\n10 0 0 10 0 0 0 40 10\n0 0 0 0 0 0 -1 20 1 # start\n";
+        let path = CameraPath::read(text.as_bytes()).unwrap();
+        assert_eq!(path.interpolation, Interpolation::Linear);
+        let middle = path.at(5.0).unwrap();
+        assert_eq!(middle.lookfrom.z, 5.0);
+        assert_eq!(middle.vfov, 30.0);
+    }
+
+    #[test]
+    fn bad_keyframes_are_errors() {
+        for text in [
+            "",
+            "# nothing\n",
+            "0 1 2 3\n",
+            "0 0 0 0 0 0 x 20 1\n",
+            "cubic\n",
+        ] {
+            let error = CameraPath::read(text.as_bytes()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{text:?}");
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{
+    hit::Hittable,
+    integrator::Integrator,
+    sampler::Sampler,
+    utils::{Color, Float, Interval, Ray},
+};
+
+/// Arbitrary output variables: what the first hit along a camera ray looks
+/// like, for inspecting a scene rather than lighting it.
+///
+/// Every pass is encoded into [0, 1] so it can be viewed directly. An `Aov`
+/// is also an `Integrator`, so a camera can render one as its main image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Aov {
+    /// World space shading normal, remapped from [-1, 1].
+    Normal,
+    /// Hit distance `d` mapped through `d / (1 + d)`, misses are black.
+    Depth,
+    /// Base color of the material that was hit.
+    Albedo,
+    /// Surface parametrization in the red and green channels.
+    Uv,
+    /// A distinct color per material instance, numbered in the order they
+    /// were added to the world. Objects made of more than one material come
+    /// out white.
+    MaterialId,
+    /// A distinct color per top level object in the world.
+    ObjectId,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 6] = [
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Albedo,
+        Aov::Uv,
+        Aov::MaterialId,
+        Aov::ObjectId,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Albedo => "albedo",
+            Aov::Uv => "uv",
+            Aov::MaterialId => "material_id",
+            Aov::ObjectId => "object_id",
+        }
+    }
+
+    /// Ids blended across an edge are no id at all, so these passes keep a
+    /// single sample per pixel instead of the average.
+    pub fn is_id(&self) -> bool {
+        matches!(self, Aov::MaterialId | Aov::ObjectId)
+    }
+
+    pub fn evaluate<T: Float>(&self, world: &dyn Hittable<T>, ray: &Ray<T>) -> Color<T> {
+        let interval = Interval::new(T::from(0.000000001).unwrap(), T::infinity());
+        let Some(rec) = world.hit(ray, interval) else {
+            return Color::splat(T::zero());
+        };
+
+        let half = T::from(0.5).unwrap();
+        match self {
+            Aov::Normal => rec.normal * half + Color::splat(half),
+            Aov::Depth => {
+                let distance = rec.t * ray.get_direction().length();
+                Color::splat(distance / (T::one() + distance))
+            }
+            Aov::Albedo => rec.material.albedo(&rec).unwrap_or(Color::splat(T::zero())),
+            Aov::Uv => Color::new(rec.u, rec.v, T::zero()),
+            Aov::MaterialId => rec.material_id.map_or(Color::splat(T::one()), id_color),
+            Aov::ObjectId => id_color(rec.object_id),
+        }
+    }
+}
+
+impl<T> Integrator<T> for Aov
+where
+    T: Float,
+{
+    fn radiance(
+        &self,
+        world: &dyn Hittable<T>,
+        ray: Ray<T>,
+        _sampler: &mut dyn Sampler,
+    ) -> Color<T> {
+        self.evaluate(world, &ray)
+    }
+}
+
+/// Scrambles an id into a color that's unlikely to match its neighbours.
+fn id_color<T: Float>(id: usize) -> Color<T> {
+    let mut h = (id as u64).wrapping_add(1).wrapping_mul(0x9e3779b97f4a7c15);
+    h ^= h >> 29;
+    h = h.wrapping_mul(0xbf58476d1ce4e5b9);
+    h ^= h >> 32;
+    let channel = |shift: u64| T::from((h >> shift) & 0xff).unwrap() / T::from(255).unwrap();
+    Color::new(channel(0), channel(8), channel(16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hit::HittableList,
+        material::{Lambertian, Material},
+        sphere::Sphere,
+        utils::{Point, Rc, Vec3},
+    };
+
+    /// A red and a blue ball side by side, then a ball of each in one object.
+    fn world() -> HittableList<f64> {
+        let red: Rc<dyn Material<f64>> = Rc::new(Lambertian::new(Color::new(1.0, 0.0, 0.0)));
+        let blue: Rc<dyn Material<f64>> = Rc::new(Lambertian::new(Color::new(0.0, 0.0, 1.0)));
+        let mut world = HittableList::new();
+        world.add(Rc::new(Sphere::new(
+            Point::new(-2.0, 0.0, 0.0),
+            1.0,
+            red.clone(),
+        )));
+        world.add(Rc::new(Sphere::new(
+            Point::new(2.0, 0.0, 0.0),
+            1.0,
+            blue.clone(),
+        )));
+        let mut both = HittableList::new();
+        both.add(Rc::new(Sphere::new(Point::new(6.0, 0.0, 0.0), 1.0, red)));
+        both.add(Rc::new(Sphere::new(Point::new(6.0, 3.0, 0.0), 1.0, blue)));
+        world.add(Rc::new(both));
+        world
+    }
+
+    /// One straight down ray per column: the red ball, a gap, the blue
+    /// ball and the mixed object.
+    fn render(aov: Aov) -> Vec<[f64; 3]> {
+        let world = world();
+        [-2.0, 0.0, 2.0, 6.0]
+            .iter()
+            .map(|&x| {
+                let ray = Ray::new(Point::new(x, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+                aov.evaluate(&world, &ray).to_array()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn passes_describe_the_first_hit() {
+        let depth = render(Aov::Depth);
+        // four units to the front of each ball
+        assert_eq!(depth[0], [0.8; 3]);
+        assert_eq!(depth[1], [0.0; 3]);
+        assert_eq!(depth[2], [0.8; 3]);
+
+        let normal = render(Aov::Normal);
+        assert_eq!(normal[0], [0.5, 0.5, 1.0]);
+        assert_eq!(normal[1], [0.0; 3]);
+
+        let material = render(Aov::MaterialId);
+        assert_ne!(material[0], material[2]);
+        assert_eq!(material[1], [0.0; 3]);
+        assert_eq!(material[3], [1.0; 3]);
+        assert!(material[0] != [1.0; 3] && material[2] != [1.0; 3]);
+
+        let object = render(Aov::ObjectId);
+        assert_ne!(object[0], object[2]);
+        assert_ne!(object[2], object[3]);
+    }
+}
@@ -1,23 +1,72 @@
 use crate::{
+    adaptive::{AdaptiveSampling, PixelStats},
+    animation::{frame_path, CameraPath, Keyframe},
+    aov::Aov,
+    checkpoint::{self, Checkpoint},
+    dither::Dither,
+    film::Film,
+    filter::Filter,
     hit::{Hittable, HittableList},
-    utils::{rand_float, write_color, Color, Interval, Point, Ray, Vec3, rand_in_unit_disk},
+    image::{Image, PnmFormat},
+    integrator::{Integrator, PathTracer},
+    lens::Lens,
+    physical::PhysicalCamera,
+    progressive::Progressive,
+    projection::Projection,
+    sampler::{Sampler, SamplerKind},
+    stereo::Stereo,
+    tile::{Region, RegionOutput, Tiling},
+    tonemap::ToneMap,
+    utils::{linear_to_srgb, luminance, Color, Interval, Point, Ray, Vec3},
 };
 use indicatif::{ProgressBar, ProgressStyle};
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    ops::{ControlFlow, RangeInclusive},
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
 #[derive(Debug)]
 pub struct Camera {
     image_width: f64,
     samples_per_pixel: i32,
-    sample_pixel_scale: f64,
     image_height: f64,
     center: Point<f64>,
+    lookat: Point<f64>,
+    vup: Vec3<f64>,
+    vfov: f64,
+    focus_dist: f64,
+    projection: Projection,
+    stereo: Option<Stereo>,
+    lens: Lens,
+    physical: Option<PhysicalCamera>,
+    // everything below is derived from the above by update_viewport
+    u: Vec3<f64>,
+    v: Vec3<f64>,
+    w: Vec3<f64>,
     pixel100_loc: Point<f64>,
     pixel_delta_u: Vec3<f64>,
     pixel_delta_v: Vec3<f64>,
     defocus_disc_u: Vec3<f64>,
     defocus_disc_v: Vec3<f64>,
     defocus_angle: f64,
-    max_depth: i32,
+    integrator: Box<dyn Integrator<f64>>,
+    sampler: SamplerKind,
+    seed: u64,
+    adaptive: Option<AdaptiveSampling>,
+    progressive: Option<Progressive>,
+    checkpoint: Option<Checkpoint>,
+    tiling: Tiling,
+    region: Option<(Region, RegionOutput)>,
+    filter: Filter,
+    tone_map: ToneMap,
+    dither: Dither,
+    format: PnmFormat,
+    aovs: Vec<Aov>,
+    aov_prefix: PathBuf,
     progress: ProgressBar,
 }
 
@@ -44,7 +93,7 @@ impl Default for Camera {
             aspect_ratio,
             image_width,
             samples_per_pixel,
-            max_depth,
+            Box::new(PathTracer::new(max_depth)),
             vfov,
             center,
             lookat,
@@ -61,7 +110,7 @@ impl Camera {
         aspect_ratio: f64,
         image_width: f64,
         samples_per_pixel: i32,
-        max_depth: i32,
+        integrator: Box<dyn Integrator<f64>>,
         vfov: f64,
         center: Vec3<f64>, // lookfrom
         lookat: Vec3<f64>,
@@ -75,30 +124,6 @@ impl Camera {
             image_height = 1.0;
         }
 
-        let theta = vfov.to_radians();
-        let h = (theta / 2.0).tan();
-        let viewport_height = 2.0 * h * focus_dist;
-        let viewport_width = aspect_ratio * viewport_height;
-
-        let w = (center - lookat).unit_vector();
-        let u = vup.cross(&w).unit_vector();
-        let v = w.cross(&u);
-
-        let viewport_u = u * viewport_width;
-        let viewport_v = v * -viewport_height;
-
-        let pixel_delta_u = viewport_u / image_width;
-        let pixel_delta_v = viewport_v / image_height;
-
-        let viewport_upper_left = center - (w * focus_dist) - viewport_u / 2.0 - viewport_v / 2.0;
-        let pixel100_loc = viewport_upper_left + (pixel_delta_u + pixel_delta_v) * 0.5;
-
-        let defocus_radius = focus_dist * (defocus_angle / 2.0).to_radians().tan();
-        let defocus_disc_u = u * defocus_radius;
-        let defocus_disc_v = v * defocus_radius;
-
-        let sample_pixel_scale = 1.0 / samples_per_pixel as f64;
-
         let progress = ProgressBar::new(image_height as u64);
         progress.set_style(
             ProgressStyle::with_template(
@@ -110,72 +135,741 @@ impl Camera {
         Self {
             image_width,
             samples_per_pixel,
-            sample_pixel_scale,
             image_height,
             center,
-            pixel100_loc,
-            pixel_delta_u,
-            pixel_delta_v,
-            defocus_disc_u,
-            defocus_disc_v,
+            lookat,
+            vup,
+            vfov,
+            focus_dist,
+            projection: Projection::default(),
+            stereo: None,
+            lens: Lens::default(),
+            physical: None,
+            u: Vec3::splat(0.0),
+            v: Vec3::splat(0.0),
+            w: Vec3::splat(0.0),
+            pixel100_loc: Point::splat(0.0),
+            pixel_delta_u: Vec3::splat(0.0),
+            pixel_delta_v: Vec3::splat(0.0),
+            defocus_disc_u: Vec3::splat(0.0),
+            defocus_disc_v: Vec3::splat(0.0),
             defocus_angle,
-            max_depth,
+            integrator,
+            sampler: SamplerKind::Sobol,
+            seed: 0,
+            adaptive: None,
+            progressive: None,
+            checkpoint: None,
+            tiling: Tiling::default(),
+            region: None,
+            filter: Filter::default(),
+            tone_map: ToneMap::default(),
+            dither: Dither::default(),
+            format: PnmFormat::default(),
+            aovs: vec![],
+            aov_prefix: PathBuf::new(),
             progress,
         }
+        .update_viewport()
     }
 
-    pub fn render(&self, world: HittableList<f64>) {
-        println!("P3\n{} {}\n255", self.image_width, self.image_height);
+    /// Works out where the pixels are in the world from the view settings.
+    fn update_viewport(mut self) -> Self {
+        let (view_width, view_height) = self.view_size();
+        let aspect_ratio = view_width / view_height;
+        if let Some(physical) = &self.physical {
+            self.vfov = physical.vfov(aspect_ratio);
+            self.defocus_angle = physical.defocus_angle();
+            self.focus_dist = physical.focus_distance;
+        }
 
-        for j in 0..self.image_height as i32 {
-            self.progress.inc(1);
-            for i in 0..self.image_width as i32 {
-                let mut color = Color::new(0.0, 0.0, 0.0);
-                for _ in 0..self.samples_per_pixel {
-                    let ray = self.get_ray(i, j);
-                    color += Self::ray_color(&world, ray, self.max_depth);
-                }
-                write_color(color * self.sample_pixel_scale)
+        let (viewport_width, viewport_height, distance) = match self.projection {
+            // the rays start on the viewport itself
+            Projection::Orthographic { view_width } => (view_width, view_width / aspect_ratio, 0.0),
+            // panoramas only use the basis, but a viewport doesn't hurt
+            _ => {
+                let h = (self.vfov.to_radians() / 2.0).tan();
+                let viewport_height = 2.0 * h * self.focus_dist;
+                (
+                    aspect_ratio * viewport_height,
+                    viewport_height,
+                    self.focus_dist,
+                )
             }
+        };
+
+        let w = (self.center - self.lookat).unit_vector();
+        let u = self.vup.cross(&w).unit_vector();
+        let v = w.cross(&u);
+
+        let viewport_u = u * viewport_width;
+        let viewport_v = v * -viewport_height;
+
+        self.pixel_delta_u = viewport_u / view_width;
+        self.pixel_delta_v = viewport_v / view_height;
+
+        let viewport_upper_left =
+            self.center - (w * distance) - viewport_u / 2.0 - viewport_v / 2.0;
+        self.pixel100_loc = viewport_upper_left + (self.pixel_delta_u + self.pixel_delta_v) * 0.5;
+
+        let defocus_radius = self.focus_dist * (self.defocus_angle / 2.0).to_radians().tan();
+        self.defocus_disc_u = u * defocus_radius;
+        self.defocus_disc_v = v * defocus_radius;
+        (self.u, self.v, self.w) = (u, v, w);
+
+        if let Some(system) = &mut self.lens.system {
+            system.fit(self.vfov, aspect_ratio, self.focus_dist);
         }
+        self
+    }
+
+    /// Changes the resolution, and with it the aspect ratio.
+    pub fn with_image_size(mut self, width: u32, height: u32) -> Self {
+        self.image_width = width.max(1) as f64;
+        self.image_height = height.max(1) as f64;
+        self.update_viewport()
+    }
+
+    pub fn with_projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self.update_viewport()
+    }
+
+    /// Renders both eyes into the image, side by side or one above the
+    /// other, so the image wants to be twice as wide or tall.
+    pub fn with_stereo(mut self, stereo: Stereo) -> Self {
+        self.stereo = Some(stereo);
+        self.update_viewport()
+    }
+
+    /// Moves the camera to where `keyframe` has it. With physical settings
+    /// only the focus is taken from it, the field of view stays theirs.
+    pub fn with_keyframe(mut self, keyframe: &Keyframe) -> Self {
+        self.center = keyframe.lookfrom;
+        self.lookat = keyframe.lookat;
+        self.vfov = keyframe.vfov;
+        self.focus_dist = keyframe.focus_dist;
+        if let Some(physical) = &mut self.physical {
+            physical.focus_distance = keyframe.focus_dist;
+        }
+        self.update_viewport()
+    }
+
+    /// Takes the field of view, depth of field and exposure from photographic
+    /// settings. The tone map's exposure adds on top as compensation.
+    pub fn with_physical(mut self, physical: PhysicalCamera) -> Self {
+        self.physical = Some(physical);
+        self.update_viewport()
+    }
+
+    /// Focuses on whatever is under pixel `(x, y)`, and leaves the focus as
+    /// it is if that's the sky.
+    pub fn with_autofocus(mut self, world: &HittableList<f64>, (x, y): (u32, u32)) -> Self {
+        let Some(ray) = self.pinhole_ray(x as f64 + 0.5, y as f64 + 0.5) else {
+            return self;
+        };
+        let Some(record) = world.hit(&ray, Interval::new(0.001, f64::INFINITY)) else {
+            return self;
+        };
+
+        // the plane in focus faces the camera, so it's the depth that counts
+        let depth = (record.point - self.center).dot(&(self.w * -1.0));
+        if depth <= 0.0 {
+            return self;
+        }
+        self.focus_dist = depth;
+        if let Some(physical) = &mut self.physical {
+            physical.focus_distance = depth;
+        }
+        self.update_viewport()
+    }
+
+    pub fn with_lens(mut self, lens: Lens) -> Self {
+        self.lens = lens;
+        self.update_viewport()
+    }
+
+    pub fn with_integrator(mut self, integrator: Box<dyn Integrator<f64>>) -> Self {
+        self.integrator = integrator;
+        self
+    }
+
+    pub fn with_samples_per_pixel(mut self, samples_per_pixel: i32) -> Self {
+        self.samples_per_pixel = samples_per_pixel;
+        self
+    }
+
+    /// Picks a different (but still reproducible) set of sample values.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_sampler(mut self, sampler: SamplerKind) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    /// Replaces the fixed `samples_per_pixel` with a per pixel sample count.
+    pub fn with_adaptive_sampling(mut self, adaptive: AdaptiveSampling) -> Self {
+        self.adaptive = Some(adaptive);
+        self
+    }
+
+    pub fn with_progressive(mut self, progressive: Progressive) -> Self {
+        self.progressive = Some(progressive);
+        self
+    }
+
+    /// Raising `samples_per_pixel` and resuming from a finished render's
+    /// checkpoint adds samples to it.
+    pub fn with_checkpoint(mut self, checkpoint: Checkpoint) -> Self {
+        self.checkpoint = Some(checkpoint);
+        self
+    }
+
+    pub fn with_tiling(mut self, tiling: Tiling) -> Self {
+        self.tiling = tiling;
+        self
+    }
+
+    /// Only renders the pixels in `region`. Whatever lies outside the image
+    /// is ignored.
+    pub fn with_region(mut self, region: Region, output: RegionOutput) -> Self {
+        self.region = Some((region, output));
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_tone_map(mut self, tone_map: ToneMap) -> Self {
+        self.tone_map = tone_map;
+        self
+    }
+
+    pub fn with_dither(mut self, dither: Dither) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    /// Format of the main image. AOVs are written in it too.
+    pub fn with_format(mut self, format: PnmFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Also write `aovs` next to the main image, each to `<prefix>_<name>.ppm`
+    /// (or `.pgm` for grayscale formats).
+    pub fn with_aovs(mut self, aovs: Vec<Aov>, prefix: impl Into<PathBuf>) -> Self {
+        self.aovs = aovs;
+        self.aov_prefix = prefix.into();
+        self
+    }
+
+    /// Renders `world` and writes the image to stdout.
+    pub fn render(&self, world: HittableList<f64>) -> io::Result<()> {
+        self.render_to(world, &mut BufWriter::new(io::stdout().lock()))
+    }
+
+    pub fn render_to_file(
+        &self,
+        world: HittableList<f64>,
+        path: impl AsRef<Path>,
+    ) -> io::Result<()> {
+        self.render_to(world, &mut BufWriter::new(File::create(path)?))
+    }
+
+    pub fn render_to(&self, world: HittableList<f64>, out: &mut impl Write) -> io::Result<()> {
+        self.render_progressive(world, out, |_, _| ControlFlow::Continue(()))
+    }
+
+    /// Renders `frames` of `path` to files named after `pattern`, see
+    /// `frame_path`. AOVs, checkpoints, snapshots and the sample heatmap get
+    /// the frame number too, so no frame overwrites another's and every
+    /// frame can be resumed on its own. Without an AOV prefix the AOVs are
+    /// named after each frame's image.
+    pub fn render_frames(
+        self,
+        world: HittableList<f64>,
+        path: &CameraPath,
+        frames: RangeInclusive<u32>,
+        pattern: impl AsRef<Path>,
+    ) -> io::Result<()> {
+        let (aov_prefix, checkpoint) = (self.aov_prefix.clone(), self.checkpoint.clone());
+        let (adaptive, progressive) = (self.adaptive.clone(), self.progressive.clone());
+        let mut camera = self;
+        for frame in frames {
+            if let Some(keyframe) = path.at(frame as f64) {
+                camera = camera.with_keyframe(&keyframe);
+            }
+            let output = frame_path(&pattern, frame);
+            camera.aov_prefix = if aov_prefix.as_os_str().is_empty() {
+                output.with_extension("")
+            } else {
+                frame_path(format!("{}_####", aov_prefix.display()), frame)
+            };
+            camera.checkpoint = checkpoint.clone().map(|checkpoint| Checkpoint {
+                path: frame_path(&checkpoint.path, frame),
+                ..checkpoint
+            });
+            camera.adaptive = adaptive.clone().map(|adaptive| {
+                match adaptive.heatmap().map(|heatmap| frame_path(heatmap, frame)) {
+                    Some(heatmap) => adaptive.with_heatmap(heatmap),
+                    None => adaptive,
+                }
+            });
+            camera.progressive = progressive.clone().map(|progressive| {
+                match progressive
+                    .snapshot()
+                    .map(|snapshot| frame_path(snapshot, frame))
+                {
+                    Some(snapshot) => {
+                        let interval = progressive.snapshot_interval();
+                        progressive.with_snapshots(snapshot, interval)
+                    }
+                    None => progressive,
+                }
+            });
+            camera.progress.reset();
+            camera.render_to_file(world.clone(), output)?;
+        }
+        Ok(())
+    }
 
+    /// Like `render_to`, but calls `on_pass` with the image so far after every
+    /// pass. Returning `ControlFlow::Break` stops the render there and writes
+    /// out what has accumulated. Without a progressive mode set the whole
+    /// render is a single pass.
+    pub fn render_progressive(
+        &self,
+        world: HittableList<f64>,
+        out: &mut impl Write,
+        mut on_pass: impl FnMut(u32, &Image) -> ControlFlow<()>,
+    ) -> io::Result<()> {
+        let mut state = match &self.checkpoint {
+            Some(checkpoint) if checkpoint.resume && checkpoint.path.exists() => {
+                RenderState::load(self, &checkpoint.path)?
+            }
+            _ => RenderState::new(self),
+        };
+        let passes = self.pass_count();
+        self.progress
+            .set_length(passes as u64 * self.render_region().pixel_count() as u64);
+
+        for pass in 1..=passes {
+            if !self.render_pass(&world, &mut state)? {
+                break;
+            }
+
+            let image = self.frame(self.develop(&state.film));
+            let snapshot = self
+                .progressive
+                .as_ref()
+                .and_then(|progressive| progressive.snapshot_path(pass));
+            if let Some(path) = snapshot {
+                replace_file(path, |partial| {
+                    image.save(partial, self.format, self.dither)
+                })?;
+            }
+            if on_pass(pass, &image).is_break() {
+                break;
+            }
+        }
         self.progress.finish();
+
+        if let Some(checkpoint) = &self.checkpoint {
+            state.save(self, &checkpoint.path)?;
+        }
+        self.write_aovs(&state)?;
+        self.frame(self.develop(&state.film))
+            .write_pnm(out, self.format, self.dither)?;
+        out.flush()
+    }
+
+    /// The film as display ready values: tone mapped and sRGB encoded. HDR
+    /// formats only get the exposure and stay linear.
+    pub fn develop(&self, film: &Film) -> Image {
+        let tone_map = ToneMap {
+            exposure: self.tone_map.exposure + self.physical.map_or(0.0, |p| p.exposure()),
+            ..self.tone_map
+        };
+        if self.format.is_hdr() {
+            let scale = tone_map.exposure.exp2();
+            return film.to_image().map(|color| color * scale);
+        }
+        film.to_image()
+            .map(|color| tone_map.apply(color).map(linear_to_srgb))
+    }
+
+    /// Size of the view the projection covers: the image, or one eye's half
+    /// of it.
+    fn view_size(&self) -> (f64, f64) {
+        match &self.stereo {
+            Some(stereo) => stereo.eye_size(self.image_width, self.image_height),
+            None => (self.image_width, self.image_height),
+        }
+    }
+
+    /// The part of the image that gets rendered.
+    fn render_region(&self) -> Region {
+        let (width, height) = (self.image_width as usize, self.image_height as usize);
+        match self.region {
+            Some((region, _)) => region.clamp(width, height),
+            None => Region::new(0, 0, width, height),
+        }
+    }
+
+    /// Cuts a full frame image down to what gets written out.
+    fn frame(&self, image: Image) -> Image {
+        match self.region {
+            Some((_, RegionOutput::Crop)) => image.crop(self.render_region()),
+            _ => image,
+        }
+    }
+
+    fn max_samples(&self) -> u32 {
+        match &self.adaptive {
+            Some(adaptive) => adaptive.max_samples(),
+            None => self.samples_per_pixel as u32,
+        }
+    }
+
+    fn pass_count(&self) -> u32 {
+        match &self.progressive {
+            Some(progressive) => self.max_samples().div_ceil(progressive.samples_per_pass()),
+            None => 1,
+        }
+    }
+
+    /// Takes up to one pass worth of samples for every pixel that still needs
+    /// them. Returns false if every pixel was already done.
+    fn render_pass(&self, world: &HittableList<f64>, state: &mut RenderState) -> io::Result<bool> {
+        let max_samples = self.max_samples();
+        let pass_samples = match &self.progressive {
+            Some(progressive) => progressive.samples_per_pass(),
+            None => max_samples,
+        };
+        let mut sampler = self.sampler.build(max_samples, state.seed);
+        let mut any_work = false;
+
+        for tile in self.tiling.tiles(self.render_region()) {
+            for (i, j) in tile.pixels() {
+                let pixel = j * self.image_width as usize + i;
+                let stats = &mut state.stats[pixel];
+                let start = stats.count();
+                let end = (start + pass_samples).min(max_samples);
+                if start >= max_samples || self.converged(stats) {
+                    continue;
+                }
+                any_work = true;
+
+                for s in start..end {
+                    sampler.start_pixel_sample((i as u32, j as u32), s);
+                    let (offset_x, offset_y) = sampler.get_2d();
+                    let (x, y) = (i as f64 + offset_x, j as f64 + offset_y);
+                    // a ray that doesn't exist still counts, as black
+                    let ray = self.get_ray(x, y, sampler.as_mut());
+                    let sample = ray.map_or(Color::splat(0.0), |(ray, weight)| {
+                        self.integrator.radiance(world, ray, sampler.as_mut()) * weight
+                    });
+                    state.film.add_sample(x, y, sample);
+                    stats.add(luminance(sample));
+                    if let Some((ray, _)) = ray {
+                        for (aov, sums) in self.aovs.iter().zip(state.aov_sums.iter_mut()) {
+                            if !aov.is_id() {
+                                sums[pixel] += aov.evaluate(world, &ray);
+                            } else if s == 0 {
+                                sums[pixel] = aov.evaluate(world, &ray);
+                            }
+                        }
+                    }
+                    if self.converged(stats) {
+                        break;
+                    }
+                }
+            }
+            self.progress.inc(tile.pixel_count() as u64);
+
+            // tiles are a safe point: every sample so far is in the film and
+            // counted against the pixel that took it
+            if let Some(checkpoint) = &self.checkpoint {
+                if state.last_save.elapsed() >= checkpoint.interval {
+                    state.save(self, &checkpoint.path)?;
+                }
+            }
+        }
+
+        Ok(any_work)
+    }
+
+    fn converged(&self, stats: &PixelStats) -> bool {
+        self.adaptive
+            .as_ref()
+            .is_some_and(|adaptive| adaptive.converged(stats))
+    }
+
+    /// Writes the AOVs and sample heatmap, if any were asked for.
+    fn write_aovs(&self, state: &RenderState) -> io::Result<()> {
+        let (width, height) = (self.image_width as usize, self.image_height as usize);
+        for (aov, sums) in self.aovs.iter().zip(&state.aov_sums) {
+            let mut image = Image::new(width, height);
+            for (pixel, (sum, stats)) in sums.iter().zip(&state.stats).enumerate() {
+                let scale = if aov.is_id() {
+                    1.0
+                } else {
+                    1.0 / stats.count().max(1) as f64
+                };
+                image.set(pixel % width, pixel / width, *sum * scale);
+            }
+            let mut path = self.aov_prefix.clone().into_os_string();
+            path.push(format!("_{}.{}", aov.name(), self.format.extension()));
+            self.frame(image).save(path, self.format, Dither::None)?;
+        }
+
+        if let Some(adaptive) = &self.adaptive {
+            if let Some(path) = adaptive.heatmap() {
+                let mut heatmap = Image::new(width, height);
+                for (pixel, stats) in state.stats.iter().enumerate() {
+                    heatmap.set(pixel % width, pixel / width, adaptive.heat(stats.count()));
+                }
+                self.frame(heatmap).save(path, self.format, Dither::None)?;
+            }
+        }
+        Ok(())
     }
 
-    fn ray_color(world: &HittableList<f64>, ray: Ray<f64>, depth: i32) -> Color<f64> {
-        if depth <= 0 {
-            return Color::new(0.0, 0.0, 0.0);
+    /// Ray through raster position `(x, y)`. The caller draws that position as
+    /// the first 2D sample, this then always draws the lens position, whether
+    /// or not there is any defocus blur, so the integrator always starts on
+    /// the same dimension. `None` where the projection doesn't cover the
+    /// image, like the corners of a fisheye. The ray comes with the weight
+    /// of its sample, which is 1 except through a traced lens.
+    fn get_ray(&self, x: f64, y: f64, sampler: &mut dyn Sampler) -> Option<(Ray<f64>, f64)> {
+        // each eye gets its own view, offset sideways from the camera
+        let (eye, x, y) = match &self.stereo {
+            Some(stereo) => stereo.eye(x, y, self.image_width, self.image_height),
+            None => (0.0, x, y),
+        };
+        let convergence = self
+            .stereo
+            .map_or(f64::INFINITY, |stereo| stereo.convergence);
+        let eye_offset = self
+            .stereo
+            .map_or(Vec3::splat(0.0), |stereo| stereo.eye_offset(eye, self.u));
+
+        let pixel_sample =
+            self.pixel100_loc + (self.pixel_delta_u * (x - 0.5)) + (self.pixel_delta_v * (y - 0.5));
+
+        let lens_sample = sampler.get_2d();
+        match self.projection {
+            Projection::Perspective => {
+                let (view_width, view_height) = self.view_size();
+                let (s, t) = (x / view_width, y / view_height);
+                if let Some(system) = &self.lens.system {
+                    let (ray, weight) = system.ray(s, t, lens_sample, &self.lens.aperture)?;
+                    let (origin, direction) = (ray.get_origin(), ray.get_direction());
+                    let ray = Ray::new(
+                        self.center + eye_offset + self.to_world(*origin),
+                        self.to_world(*direction),
+                    );
+                    return Some((ray, weight));
+                }
+
+                // off axis: both eyes see the same rectangle at the
+                // convergence distance, which works out to shifting the
+                // point in focus by part of the eye offset
+                let focus_point = pixel_sample + eye_offset * (1.0 - self.focus_dist / convergence);
+                let ray_origin = if self.defocus_angle <= 0.0 {
+                    self.center
+                } else {
+                    // measured across the shorter side, so the cat's eye is
+                    // the same both ways
+                    let scale = view_width.min(view_height);
+                    let screen = (
+                        (2.0 * x - view_width) / scale,
+                        (view_height - 2.0 * y) / scale,
+                    );
+                    let p = self.lens.sample(lens_sample, screen)?;
+                    self.center + (self.defocus_disc_u * p.x) + (self.defocus_disc_v * p.y)
+                } + eye_offset;
+                let ray_direction = focus_point - ray_origin;
+                Some((Ray::new(ray_origin, ray_direction), 1.0))
+            }
+            Projection::Orthographic { .. } => {
+                Some((Ray::new(pixel_sample + eye_offset, self.w * -1.0), 1.0))
+            }
+            // panoramas all come from a single point, or for ODS from a
+            // circle with the eyes at right angles to the view direction
+            panoramic => {
+                let (view_width, view_height) = self.view_size();
+                let (s, t) = (x / view_width, y / view_height);
+                let local = panoramic.direction(s, t, view_width / view_height)?;
+                let direction = self.to_world(local);
+
+                let offset = match (panoramic, &self.stereo) {
+                    (Projection::Equirectangular, Some(stereo)) => stereo
+                        .ods_offset(eye, direction, self.v)
+                        .unwrap_or(eye_offset),
+                    _ => eye_offset,
+                };
+                let direction = if convergence.is_finite() {
+                    direction * convergence - offset
+                } else {
+                    direction
+                };
+                Some((Ray::new(self.center + offset, direction), 1.0))
+            }
         }
-        let interval = Interval::new(0.000000001, f64::INFINITY);
-        if let Some(rec) = world.hit(&ray, interval) {
-            if let Some((attenuation, scattered)) = rec.material.scatter(&ray, &rec) {
-                let ray_color = Self::ray_color(world, scattered, depth - 1);
-                return attenuation.mul_vec3(ray_color);
+    }
+
+    /// Ray through raster position `(x, y)` from the middle of the lens, for
+    /// the left eye if there are two.
+    fn pinhole_ray(&self, x: f64, y: f64) -> Option<Ray<f64>> {
+        let (view_width, view_height) = self.view_size();
+        let (x, y) = (x.min(view_width), y.min(view_height));
+        let pixel_sample =
+            self.pixel100_loc + (self.pixel_delta_u * (x - 0.5)) + (self.pixel_delta_v * (y - 0.5));
+        match self.projection {
+            Projection::Perspective => Some(Ray::new(self.center, pixel_sample - self.center)),
+            Projection::Orthographic { .. } => Some(Ray::new(pixel_sample, self.w * -1.0)),
+            panoramic => {
+                let local = panoramic.direction(
+                    x / view_width,
+                    y / view_height,
+                    view_width / view_height,
+                )?;
+                Some(Ray::new(self.center, self.to_world(local)))
             }
-            return Color::new(0.0, 0.0, 0.0);
         }
-        let unit_direction = ray.get_direction().unit_vector();
-        let a = (unit_direction.y + 1.0) * 0.5;
-        Color::new(1.0, 1.0, 1.0) * (1.0 - a) + Color::new(0.5, 0.7, 1.0) * a
     }
 
-    fn get_ray(&self, i: i32, j: i32) -> Ray<f64> {
-        let offset = Self::sample_square();
-        let pixel_sample = self.pixel100_loc
-            + (self.pixel_delta_u * (i as f64 + offset.x))
-            + (self.pixel_delta_v * (j as f64 + offset.y));
+    /// From the camera's frame (x right, y up, z behind) to the world's.
+    fn to_world(&self, local: Vec3<f64>) -> Vec3<f64> {
+        self.u * local.x + self.v * local.y + self.w * local.z
+    }
+}
+
+/// Writes `path` by way of a temporary file next to it, so nothing reading
+/// it ever sees half a file and a crash midway leaves the old one intact.
+fn replace_file(path: &Path, write: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    let mut partial = OsString::from(path.as_os_str());
+    partial.push(".partial");
+    write(Path::new(&partial))?;
+    fs::rename(&partial, path)
+}
+
+/// Everything accumulated for the image so far.
+#[derive(Debug)]
+struct RenderState {
+    film: Film,
+    stats: Vec<PixelStats>,
+    /// Per pixel sums of every AOV, divided by the sample count on output.
+    aov_sums: Vec<Vec<Color<f64>>>,
+    seed: u64,
+    last_save: Instant,
+}
 
-        let ray_origin = if self.defocus_angle <= 0.0 { self.center } else { self.defocus_disk_sample() };
-        let ray_direction = pixel_sample - ray_origin;
-        Ray::new(ray_origin, ray_direction)
+impl RenderState {
+    fn new(camera: &Camera) -> RenderState {
+        let (width, height) = (camera.image_width as usize, camera.image_height as usize);
+        Self {
+            film: Film::new(width, height, camera.filter).with_window(camera.render_region()),
+            stats: vec![PixelStats::default(); width * height],
+            aov_sums: vec![vec![Color::splat(0.0); width * height]; camera.aovs.len()],
+            seed: camera.seed,
+            last_save: Instant::now(),
+        }
     }
 
-    fn defocus_disk_sample(&self) -> Vec3<f64> {
-        let p = rand_in_unit_disk();
-        self.center + (self.defocus_disc_u * p.x) + (self.defocus_disc_v * p.y)
+    /// Picks up a checkpoint written by `save`. The image size, AOVs, filter
+    /// and sampler have to match the camera's, and the samples per pixel can
+    /// only go up. The checkpoint's seed wins over the camera's so the sample
+    /// sequences carry on where they were.
+    fn load(camera: &Camera, path: &Path) -> io::Result<RenderState> {
+        let mut input = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != checkpoint::MAGIC {
+            return Err(checkpoint::invalid("not a render checkpoint"));
+        }
+
+        let mut state = Self::new(camera);
+        let width = checkpoint::read_u64(&mut input)?;
+        let height = checkpoint::read_u64(&mut input)?;
+        let aov_count = checkpoint::read_u64(&mut input)?;
+        if (width, height) != (camera.image_width as u64, camera.image_height as u64) {
+            return Err(checkpoint::invalid(
+                "checkpoint is for a different image size",
+            ));
+        }
+        if aov_count != camera.aovs.len() as u64 {
+            return Err(checkpoint::invalid("checkpoint has different AOVs"));
+        }
+        if Filter::read_state(&mut input)? != camera.filter {
+            return Err(checkpoint::invalid("checkpoint has a different filter"));
+        }
+        if SamplerKind::read_state(&mut input)? != camera.sampler {
+            return Err(checkpoint::invalid("checkpoint has a different sampler"));
+        }
+        let samples = checkpoint::read_u64(&mut input)?;
+        let max_samples = camera.max_samples() as u64;
+        if samples > max_samples {
+            return Err(checkpoint::invalid(
+                "checkpoint has more samples per pixel than asked for",
+            ));
+        }
+        if samples != max_samples && camera.sampler.depends_on_count() {
+            return Err(checkpoint::invalid(
+                "checkpoint has a different sample count, which the sampler depends on",
+            ));
+        }
+        state.seed = checkpoint::read_u64(&mut input)?;
+
+        state.film.read_state(&mut input)?;
+        for stats in state.stats.iter_mut() {
+            *stats = PixelStats::read_state(&mut input)?;
+        }
+        for sums in state.aov_sums.iter_mut() {
+            for sum in sums.iter_mut() {
+                *sum = Color::new(
+                    checkpoint::read_f64(&mut input)?,
+                    checkpoint::read_f64(&mut input)?,
+                    checkpoint::read_f64(&mut input)?,
+                );
+            }
+        }
+        Ok(state)
     }
 
-    fn sample_square() -> Vec3<f64> {
-        Vec3::new(rand_float() - 0.5, rand_float() - 0.5, 0.0)
+    fn save(&mut self, camera: &Camera, path: &Path) -> io::Result<()> {
+        replace_file(path, |partial| {
+            let mut out = BufWriter::new(File::create(partial)?);
+            out.write_all(checkpoint::MAGIC)?;
+            checkpoint::write_u64(&mut out, self.film.width() as u64)?;
+            checkpoint::write_u64(&mut out, self.film.height() as u64)?;
+            checkpoint::write_u64(&mut out, self.aov_sums.len() as u64)?;
+            camera.filter.write_state(&mut out)?;
+            camera.sampler.write_state(&mut out)?;
+            checkpoint::write_u64(&mut out, camera.max_samples() as u64)?;
+            checkpoint::write_u64(&mut out, self.seed)?;
+
+            self.film.write_state(&mut out)?;
+            for stats in &self.stats {
+                stats.write_state(&mut out)?;
+            }
+            for sums in &self.aov_sums {
+                for sum in sums {
+                    for value in sum.to_array() {
+                        checkpoint::write_f64(&mut out, value)?;
+                    }
+                }
+            }
+            out.flush()
+        })?;
+        self.last_save = Instant::now();
+        Ok(())
     }
 }
@@ -0,0 +1,63 @@
+use std::{
+    io::{self, Read, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+/// Periodically saves everything a render has accumulated so it can pick up
+/// where it left off after being killed, or take more samples once finished.
+///
+/// The samplers are deterministic in the seed, pixel and sample index, so the
+/// per pixel sample counts stored alongside the framebuffer are all the random
+/// state there is. The scene has to be the same when resuming, and so do the
+/// filter and sampler, which the checkpoint checks. The sample count can go
+/// up, to add samples to a finished render, unless the sampler's pattern
+/// depends on it.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub path: PathBuf,
+    pub interval: Duration,
+    /// Continue from `path` if it exists instead of starting over.
+    pub resume: bool,
+}
+
+impl Checkpoint {
+    pub fn new(path: impl Into<PathBuf>, interval: Duration) -> Checkpoint {
+        Self {
+            path: path.into(),
+            interval,
+            resume: false,
+        }
+    }
+
+    pub fn resume(mut self) -> Self {
+        self.resume = true;
+        self
+    }
+}
+
+pub const MAGIC: &[u8; 8] = b"RTCKPT02";
+
+pub fn write_u64(out: &mut impl Write, value: u64) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+pub fn write_f64(out: &mut impl Write, value: f64) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+pub fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn read_f64(input: &mut impl Read) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+pub fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -1,29 +1,107 @@
-use crate::{interval::Interval, utils::Float, vec3::Vec3};
+use crate::{utils::Float, vec3::Vec3};
 
 pub type Color<T> = Vec3<T>;
 
-fn gamma_correct<T: Float>(linear: T) -> T {
-    if linear > T::from(0.0).unwrap() {
-        return linear.sqrt();
+/// Relative luminance of a linear Rec. 709 color.
+pub fn luminance<T: Float>(color: Color<T>) -> T {
+    color.x * T::from(0.2126).unwrap()
+        + color.y * T::from(0.7152).unwrap()
+        + color.z * T::from(0.0722).unwrap()
+}
+
+/// The sRGB transfer function (OETF), linear to display encoded.
+pub fn linear_to_srgb<T: Float>(linear: T) -> T {
+    if linear <= T::from(0.0031308).unwrap() {
+        return linear.max(T::zero()) * T::from(12.92).unwrap();
     }
-    T::from(0.0).unwrap()
+    T::from(1.055).unwrap() * linear.powf(T::from(1.0 / 2.4).unwrap()) - T::from(0.055).unwrap()
 }
 
-pub fn write_color<T>(color: Color<T>)
+/// Maps `value` in [0, 1] onto the integers `0..=max`, rounding to the
+/// nearest level. `dither` is added in units of one level before rounding.
+pub fn quantize(value: f64, max: u32, dither: f64) -> u32 {
+    let level = (value.clamp(0.0, 1.0) * max as f64 + 0.5 + dither).floor();
+    level.clamp(0.0, max as f64) as u32
+}
+
+/// sRGB encodes a linear color and quantizes it to 8 bits per channel.
+pub fn to_rgb8<T>(color: Color<T>, dither: f64) -> [u8; 3]
 where
     T: Float,
 {
-    let (r, g, b) = (
-        gamma_correct(color.x),
-        gamma_correct(color.y),
-        gamma_correct(color.z),
-    );
-    let intensity: Interval<T> = Interval::new(T::from(0.0).unwrap(), T::from(0.99999).unwrap());
-    let num = T::from(260.0).unwrap();
-
-    let r: u32 = num_traits::cast(num * intensity.clamp(r)).unwrap();
-    let g: u32 = num_traits::cast(num * intensity.clamp(g)).unwrap();
-    let b: u32 = num_traits::cast(num * intensity.clamp(b)).unwrap();
-
-    println!("{r} {g} {b}");
+    color
+        .map(|c| {
+            let encoded = linear_to_srgb(c).to_f64().unwrap_or(0.0);
+            quantize(encoded, 255, dither) as u8
+        })
+        .to_array()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantize_maps_the_ends_exactly() {
+        assert_eq!(quantize(0.0, 255, 0.0), 0);
+        assert_eq!(quantize(1.0, 255, 0.0), 255);
+        assert_eq!(quantize(1.0, 65535, 0.0), 65535);
+    }
+
+    #[test]
+    fn quantize_clamps_out_of_range_values() {
+        assert_eq!(quantize(-0.3, 255, 0.0), 0);
+        assert_eq!(quantize(7.0, 255, 0.0), 255);
+        assert_eq!(quantize(0.0, 255, -0.49), 0);
+        assert_eq!(quantize(1.0, 255, 0.49), 255);
+    }
+
+    #[test]
+    fn quantize_round_trips_every_level() {
+        for level in 0..=255 {
+            assert_eq!(quantize(level as f64 / 255.0, 255, 0.0), level);
+        }
+    }
+
+    #[test]
+    fn quantize_gives_every_level_an_equal_share() {
+        // the levels at the ends only get half a step, like rounding should
+        let steps = 255 * 100;
+        let mut counts = [0; 256];
+        for i in 0..steps {
+            counts[quantize((i as f64 + 0.5) / steps as f64, 255, 0.0) as usize] += 1;
+        }
+        assert_eq!(counts[0], 50);
+        assert_eq!(counts[255], 50);
+        assert!(counts[1..255].iter().all(|&c| c == 100));
+    }
+
+    #[test]
+    fn dithering_moves_at_most_one_level() {
+        for i in 0..=1000 {
+            let value = i as f64 / 1000.0;
+            let plain = quantize(value, 255, 0.0) as i64;
+            for dither in [-0.5, -0.25, 0.0, 0.25, 0.4999] {
+                let dithered = quantize(value, 255, dither) as i64;
+                assert!((dithered - plain).abs() <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn dithering_preserves_the_average() {
+        let value = 100.3 / 255.0;
+        let offsets: Vec<f64> = (0..64).map(|i| (i as f64 + 0.5) / 64.0 - 0.5).collect();
+        let mean = offsets
+            .iter()
+            .map(|&d| quantize(value, 255, d) as f64)
+            .sum::<f64>()
+            / offsets.len() as f64;
+        assert!((mean - 100.3).abs() < 1.0 / 64.0 + 1e-9);
+    }
+
+    #[test]
+    fn to_rgb8_applies_the_srgb_curve() {
+        assert_eq!(to_rgb8(Color::new(0.0, 1.0, 0.5), 0.0), [0, 255, 188]);
+    }
 }
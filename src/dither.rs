@@ -0,0 +1,173 @@
+use std::sync::OnceLock;
+
+/// Sub-step noise added before quantizing so smooth gradients, like the sky,
+/// don't turn into visible bands.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Dither {
+    #[default]
+    None,
+    /// 8x8 Bayer matrix.
+    Ordered,
+    /// 64x64 void-and-cluster blue noise mask.
+    BlueNoise,
+}
+
+impl Dither {
+    /// Offset for pixel `(x, y)`, in [-0.5, 0.5) of a quantization step.
+    pub fn offset(&self, x: usize, y: usize) -> f64 {
+        match self {
+            Dither::None => 0.0,
+            Dither::Ordered => (BAYER[y % 8][x % 8] as f64 + 0.5) / 64.0 - 0.5,
+            Dither::BlueNoise => {
+                let mask = blue_noise();
+                let rank = mask[(y % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + x % BLUE_NOISE_SIZE];
+                (rank as f64 + 0.5) / mask.len() as f64 - 0.5
+            }
+        }
+    }
+}
+
+const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// The rank of every pixel in the blue noise mask, built on first use.
+fn blue_noise() -> &'static [u16] {
+    static MASK: OnceLock<Vec<u16>> = OnceLock::new();
+    MASK.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.5))
+}
+
+/// Ulichney's void-and-cluster method: orders the pixels of a `size` x `size`
+/// tile so that every prefix of the order is as evenly spread as possible.
+fn void_and_cluster(size: usize, sigma: f64) -> Vec<u16> {
+    let n = size * size;
+
+    // gaussian falloff by toroidal offset, so the mask tiles seamlessly
+    let mut kernel = vec![0.0; n];
+    for dy in 0..size {
+        for dx in 0..size {
+            let x = dx.min(size - dx) as f64;
+            let y = dy.min(size - dy) as f64;
+            kernel[dy * size + dx] = (-(x * x + y * y) / (2.0 * sigma * sigma)).exp();
+        }
+    }
+
+    let mut ones = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let toggle = |ones: &mut [bool], energy: &mut [f64], idx: usize| {
+        ones[idx] = !ones[idx];
+        let sign = if ones[idx] { 1.0 } else { -1.0 };
+        let (px, py) = (idx % size, idx / size);
+        for y in 0..size {
+            for x in 0..size {
+                let k = ((y + size - py) % size) * size + (x + size - px) % size;
+                energy[y * size + x] += sign * kernel[k];
+            }
+        }
+    };
+    let tightest_cluster = |ones: &[bool], energy: &[f64]| {
+        (0..n)
+            .filter(|&i| ones[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+    let largest_void = |ones: &[bool], energy: &[f64]| {
+        (0..n)
+            .filter(|&i| !ones[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    // initial pattern: a tenth of the pixels at (deterministic) random
+    let mut state = 0x853c49e6748fea9b_u64;
+    let initial = n / 10;
+    let mut placed = 0;
+    while placed < initial {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let idx = (state >> 33) as usize % n;
+        if !ones[idx] {
+            toggle(&mut ones, &mut energy, idx);
+            placed += 1;
+        }
+    }
+
+    // relax it until moving the tightest cluster into the largest void is a no-op
+    loop {
+        let cluster = tightest_cluster(&ones, &energy);
+        toggle(&mut ones, &mut energy, cluster);
+        let void = largest_void(&ones, &energy);
+        if void == cluster {
+            toggle(&mut ones, &mut energy, cluster);
+            break;
+        }
+        toggle(&mut ones, &mut energy, void);
+    }
+
+    let mut ranks = vec![0; n];
+
+    // rank the initial points by taking them away again, tightest first
+    let (mut prototype, mut prototype_energy) = (ones.clone(), energy.clone());
+    for rank in (0..initial).rev() {
+        let cluster = tightest_cluster(&prototype, &prototype_energy);
+        toggle(&mut prototype, &mut prototype_energy, cluster);
+        ranks[cluster] = rank as u16;
+    }
+
+    // then everything else by filling the largest void each time
+    for rank in initial..n {
+        let void = largest_void(&ones, &energy);
+        toggle(&mut ones, &mut energy, void);
+        ranks[void] = rank as u16;
+    }
+
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_stay_within_half_a_step() {
+        for dither in [Dither::None, Dither::Ordered, Dither::BlueNoise] {
+            for y in 0..70 {
+                for x in 0..70 {
+                    let offset = dither.offset(x, y);
+                    assert!((-0.5..0.5).contains(&offset), "{dither:?} {offset}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn masks_use_every_threshold_once() {
+        let mut bayer: Vec<_> = BAYER.iter().flatten().copied().collect();
+        bayer.sort();
+        assert!(bayer.iter().enumerate().all(|(i, &v)| v as usize == i));
+
+        let mut blue_noise = blue_noise().to_vec();
+        blue_noise.sort();
+        assert!(blue_noise.iter().enumerate().all(|(i, &v)| v as usize == i));
+    }
+
+    #[test]
+    fn offsets_average_to_zero_over_a_tile() {
+        for (dither, size) in [(Dither::Ordered, 8), (Dither::BlueNoise, BLUE_NOISE_SIZE)] {
+            let sum: f64 = (0..size)
+                .flat_map(|y| (0..size).map(move |x| dither.offset(x, y)))
+                .sum();
+            assert!(sum.abs() < 1e-9, "{dither:?} {sum}");
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{
+    checkpoint::{read_f64, write_f64},
+    filter::Filter,
+    image::Image,
+    tile::Region,
+    utils::Color,
+};
+use std::io::{self, Read, Write};
+
+#[derive(Debug, Copy, Clone)]
+struct FilmPixel {
+    weighted_sum: Color<f64>,
+    weight_sum: f64,
+}
+
+impl Default for FilmPixel {
+    fn default() -> Self {
+        Self {
+            weighted_sum: Color::splat(0.0),
+            weight_sum: 0.0,
+        }
+    }
+}
+
+/// Accumulates radiance samples into pixels. Each sample is splatted onto
+/// every pixel within the filter's radius, weighted by the filter.
+#[derive(Debug, Clone)]
+pub struct Film {
+    width: usize,
+    height: usize,
+    filter: Filter,
+    /// Samples only splat onto pixels in here.
+    window: Region,
+    pixels: Vec<FilmPixel>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Filter) -> Film {
+        Self {
+            width,
+            height,
+            filter,
+            window: Region::new(0, 0, width, height),
+            pixels: vec![FilmPixel::default(); width * height],
+        }
+    }
+
+    /// Keeps samples near the edge of `window` from bleeding out of it, for
+    /// when only part of the image is rendered.
+    pub fn with_window(mut self, window: Region) -> Self {
+        self.window = window.clamp(self.width, self.height);
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Adds a sample taken at raster position `(x, y)`, where pixel `(i, j)`
+    /// covers `[i, i + 1) x [j, j + 1)`.
+    pub fn add_sample(&mut self, x: f64, y: f64, color: Color<f64>) {
+        let radius = self.filter.radius();
+        let window = self.window;
+        let x0 = (x - 0.5 - radius).ceil().max(window.x as f64) as usize;
+        let y0 = (y - 0.5 - radius).ceil().max(window.y as f64) as usize;
+        let x1 = ((x - 0.5 + radius).floor() as isize).min((window.x + window.width) as isize - 1);
+        let y1 = ((y - 0.5 + radius).floor() as isize).min((window.y + window.height) as isize - 1);
+
+        for py in y0 as isize..=y1 {
+            for px in x0 as isize..=x1 {
+                let weight = self
+                    .filter
+                    .evaluate(px as f64 + 0.5 - x, py as f64 + 0.5 - y);
+                if weight == 0.0 {
+                    continue;
+                }
+                let pixel = &mut self.pixels[py as usize * self.width + px as usize];
+                pixel.weighted_sum += color * weight;
+                pixel.weight_sum += weight;
+            }
+        }
+    }
+
+    /// The filtered value of pixel `(x, y)`, black if nothing landed near it.
+    pub fn pixel(&self, x: usize, y: usize) -> Color<f64> {
+        let pixel = &self.pixels[y * self.width + x];
+        if pixel.weight_sum.abs() < 1e-12 {
+            return Color::splat(0.0);
+        }
+        pixel.weighted_sum / pixel.weight_sum
+    }
+
+    /// Dumps the raw accumulated sums, for checkpoints.
+    pub fn write_state(&self, out: &mut impl Write) -> io::Result<()> {
+        for pixel in &self.pixels {
+            for value in pixel.weighted_sum.to_array() {
+                write_f64(out, value)?;
+            }
+            write_f64(out, pixel.weight_sum)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the accumulated sums with ones from `write_state` for a film
+    /// of the same size.
+    pub fn read_state(&mut self, input: &mut impl Read) -> io::Result<()> {
+        for pixel in self.pixels.iter_mut() {
+            pixel.weighted_sum = Color::new(read_f64(input)?, read_f64(input)?, read_f64(input)?);
+            pixel.weight_sum = read_f64(input)?;
+        }
+        Ok(())
+    }
+
+    pub fn to_image(&self) -> Image {
+        let mut image = Image::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                image.set(x, y, self.pixel(x, y));
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide_filters_stay_inside_the_window() {
+        let mut film = Film::new(8, 8, Filter::gaussian(2.0)).with_window(Region::new(2, 2, 4, 4));
+        film.add_sample(2.1, 3.5, Color::splat(1.0));
+        assert_eq!(film.pixel(1, 3).to_array(), [0.0; 3]);
+        assert_eq!(film.pixel(2, 3).to_array(), [1.0; 3]);
+        assert_eq!(film.pixel(3, 4).to_array(), [1.0; 3]);
+    }
+}
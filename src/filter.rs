@@ -0,0 +1,200 @@
+use crate::{
+    checkpoint::{invalid, read_f64, read_u64, write_f64, write_u64},
+    utils::PI,
+};
+use std::io::{self, Read, Write};
+
+/// Pixel reconstruction filters. Each one is separable, centred on the
+/// pixel and integrates to 1, with `radius` measured in pixels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Filter {
+    Box {
+        radius: f64,
+    },
+    Tent {
+        radius: f64,
+    },
+    Gaussian {
+        radius: f64,
+        sigma: f64,
+    },
+    /// Mitchell-Netravali cubic, `b = c = 1/3` is the usual choice.
+    Mitchell {
+        radius: f64,
+        b: f64,
+        c: f64,
+    },
+    /// Sinc windowed by a wider sinc with `tau` lobes either side, which
+    /// end `tau` pixels out, so that's its radius too.
+    Lanczos {
+        tau: f64,
+    },
+}
+
+impl Default for Filter {
+    /// A unit box, every sample only counts towards the pixel it landed in.
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+impl Filter {
+    pub fn gaussian(radius: f64) -> Filter {
+        Filter::Gaussian {
+            radius,
+            sigma: radius / 3.0,
+        }
+    }
+
+    pub fn mitchell(radius: f64) -> Filter {
+        Filter::Mitchell {
+            radius,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        }
+    }
+
+    pub fn lanczos(tau: f64) -> Filter {
+        Filter::Lanczos { tau }
+    }
+
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { tau: radius } => radius,
+        }
+    }
+
+    /// A tag for the kind of filter and its three parameters at most, zero
+    /// padded.
+    pub fn write_state(&self, out: &mut impl Write) -> io::Result<()> {
+        let (tag, params) = match *self {
+            Filter::Box { radius } => (0, [radius, 0.0, 0.0]),
+            Filter::Tent { radius } => (1, [radius, 0.0, 0.0]),
+            Filter::Gaussian { radius, sigma } => (2, [radius, sigma, 0.0]),
+            Filter::Mitchell { radius, b, c } => (3, [radius, b, c]),
+            Filter::Lanczos { tau } => (4, [tau, 0.0, 0.0]),
+        };
+        write_u64(out, tag)?;
+        params.iter().try_for_each(|&param| write_f64(out, param))
+    }
+
+    pub fn read_state(input: &mut impl Read) -> io::Result<Filter> {
+        let tag = read_u64(input)?;
+        let [radius, a, b] = [read_f64(input)?, read_f64(input)?, read_f64(input)?];
+        Ok(match tag {
+            0 => Filter::Box { radius },
+            1 => Filter::Tent { radius },
+            2 => Filter::Gaussian { radius, sigma: a },
+            3 => Filter::Mitchell { radius, b: a, c: b },
+            4 => Filter::Lanczos { tau: radius },
+            _ => return Err(invalid("unknown filter")),
+        })
+    }
+
+    /// Weight of a sample at offset `(x, y)` from the pixel centre.
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        if x > self.radius() {
+            return 0.0;
+        }
+        match *self {
+            Filter::Box { radius } => 1.0 / (2.0 * radius),
+            Filter::Tent { radius } => (radius - x) / (radius * radius),
+            Filter::Gaussian { radius, sigma } => {
+                // less the value at the edge, so it goes smoothly to 0 there
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                let area = sigma * (2.0 * PI).sqrt() * erf(radius / (sigma * 2.0_f64.sqrt()))
+                    - 2.0 * radius * gaussian(radius);
+                (gaussian(x) - gaussian(radius)).max(0.0) / area
+            }
+            // the cubic covers [-2, 2] with unit area
+            Filter::Mitchell { radius, b, c } => mitchell_1d(2.0 * x / radius, b, c) * 2.0 / radius,
+            // close enough to unit area not to bother
+            Filter::Lanczos { tau } => sinc(x) * sinc(x / tau),
+        }
+    }
+}
+
+/// The Mitchell-Netravali cubic over [0, 2].
+fn mitchell_1d(x: f64, b: f64, c: f64) -> f64 {
+    if x > 1.0 {
+        ((-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b))
+            / 6.0
+    }
+}
+
+/// Abramowitz and Stegun 7.1.26, good to about 1e-7.
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    (1.0 - poly * (-x * x).exp()).copysign(x)
+}
+
+fn sinc(x: f64) -> f64 {
+    if x < 1e-5 {
+        return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filters() -> [Filter; 6] {
+        [
+            Filter::default(),
+            Filter::Box { radius: 1.5 },
+            Filter::Tent { radius: 1.5 },
+            Filter::gaussian(2.0),
+            Filter::mitchell(2.0),
+            Filter::lanczos(3.0),
+        ]
+    }
+
+    #[test]
+    fn filters_integrate_to_one() {
+        for filter in filters() {
+            let radius = filter.radius();
+            let steps = 400;
+            let step = 2.0 * radius / steps as f64;
+            let mut sum = 0.0;
+            for i in 0..steps {
+                for j in 0..steps {
+                    let x = -radius + (i as f64 + 0.5) * step;
+                    let y = -radius + (j as f64 + 0.5) * step;
+                    sum += filter.evaluate(x, y) * step * step;
+                }
+            }
+            assert!((sum - 1.0).abs() < 0.02, "{filter:?} {sum}");
+        }
+    }
+
+    #[test]
+    fn filters_stop_at_their_radius() {
+        for filter in filters() {
+            let outside = filter.radius() + 1e-3;
+            assert_eq!(filter.evaluate(outside, 0.0), 0.0, "{filter:?}");
+            assert_eq!(filter.evaluate(0.0, -outside), 0.0, "{filter:?}");
+            assert!(filter.evaluate(0.0, 0.0) > 0.0, "{filter:?}");
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{
     material::Material,
+    sampler::Sampler,
     utils::{Interval, Point, Ray, Rc, Vec3},
 };
 use num_traits::Float;
@@ -9,7 +10,15 @@ pub struct HitRecord<T> {
     pub point: Point<T>,
     pub normal: Vec3<T>,
     pub t: T,
+    pub u: T,
+    pub v: T,
     pub material: Rc<dyn Material<T>>,
+    /// Index of the top level object in the `HittableList` that was hit.
+    pub object_id: usize,
+    /// Index of the material among the distinct ones in the `HittableList`,
+    /// in the order they were added. `None` for objects made of more than
+    /// one material.
+    pub material_id: Option<usize>,
     front: Option<bool>,
 }
 
@@ -21,6 +30,7 @@ where
         point: Point<T>,
         normal: Vec3<T>,
         t: T,
+        (u, v): (T, T),
         ray: &Ray<T>,
         material: Rc<dyn Material<T>>,
     ) -> HitRecord<T> {
@@ -28,8 +38,12 @@ where
             point,
             normal,
             t,
+            u,
+            v,
             front: None,
             material,
+            object_id: 0,
+            material_id: None,
         };
         instance.set_front_face(ray, normal);
         instance
@@ -53,10 +67,35 @@ where
 
 pub trait Hittable<T> {
     fn hit(&self, ray: &Ray<T>, ray_t: Interval<T>) -> Option<HitRecord<T>>;
+
+    /// Solid angle density of `random` picking `direction` from `origin`.
+    fn pdf_value(&self, _origin: &Point<T>, _direction: &Vec3<T>) -> T
+    where
+        T: Float,
+    {
+        T::zero()
+    }
+
+    /// A direction from `origin` towards a random point on the object.
+    fn random(&self, _origin: &Point<T>, _sampler: &mut dyn Sampler) -> Vec3<T>
+    where
+        T: Float,
+    {
+        Vec3::new(T::one(), T::zero(), T::zero())
+    }
+
+    /// The material the whole object is made of, if there's just one.
+    fn material(&self) -> Option<Rc<dyn Material<T>>> {
+        None
+    }
 }
 
+#[derive(Clone)]
 pub struct HittableList<T> {
     objects: Vec<Rc<dyn Hittable<T>>>,
+    /// Per object, the id of its material if it has a single one.
+    material_ids: Vec<Option<usize>>,
+    materials: Vec<Rc<dyn Material<T>>>,
 }
 
 impl<T> Default for HittableList<T> {
@@ -67,34 +106,109 @@ impl<T> Default for HittableList<T> {
 
 impl<T> HittableList<T> {
     pub fn new() -> HittableList<T> {
-        Self { objects: vec![] }
+        Self {
+            objects: vec![],
+            material_ids: vec![],
+            materials: vec![],
+        }
     }
 
     pub fn add(&mut self, obj: Rc<dyn Hittable<T>>) {
+        // ids by first appearance, so they're the same every run
+        let material_id = obj.material().map(|material| {
+            let address = |m: &Rc<dyn Material<T>>| Rc::as_ptr(m) as *const ();
+            match self
+                .materials
+                .iter()
+                .position(|m| address(m) == address(&material))
+            {
+                Some(id) => id,
+                None => {
+                    self.materials.push(material);
+                    self.materials.len() - 1
+                }
+            }
+        });
         self.objects.push(obj);
+        self.material_ids.push(material_id);
     }
 
     pub fn clear(&mut self) {
         self.objects.clear();
+        self.material_ids.clear();
+        self.materials.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
     }
 }
 
 impl<T> Hittable<T> for HittableList<T>
 where
-    T: Copy,
+    T: Float,
 {
     fn hit(&self, ray: &Ray<T>, ray_t: Interval<T>) -> Option<HitRecord<T>> {
         let mut record: Option<HitRecord<T>> = None;
         let mut closest: T = ray_t.max;
 
-        for object in self.objects.iter() {
+        for (id, object) in self.objects.iter().enumerate() {
             let interval = Interval::new(ray_t.min, closest);
-            if let Some(r) = object.hit(ray, interval) {
-                record = Some(r.clone());
-                closest = record.as_ref().unwrap().t;
+            if let Some(mut r) = object.hit(ray, interval) {
+                r.object_id = id;
+                r.material_id = self.material_ids[id];
+                closest = r.t;
+                record = Some(r);
             }
         }
 
         record
     }
+
+    fn pdf_value(&self, origin: &Point<T>, direction: &Vec3<T>) -> T {
+        if self.objects.is_empty() {
+            return T::zero();
+        }
+        let weight = T::one() / T::from(self.objects.len()).unwrap();
+        self.objects.iter().fold(T::zero(), |sum, object| {
+            sum + weight * object.pdf_value(origin, direction)
+        })
+    }
+
+    fn random(&self, origin: &Point<T>, sampler: &mut dyn Sampler) -> Vec3<T> {
+        let idx = (sampler.get_1d() * self.objects.len() as f64) as usize;
+        self.objects[idx.min(self.objects.len() - 1)].random(origin, sampler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lambertian, sphere::Sphere, utils::Color};
+
+    #[test]
+    fn material_ids_follow_the_order_materials_were_added() {
+        let red: Rc<dyn Material<f64>> = Rc::new(Lambertian::new(Color::new(1.0, 0.0, 0.0)));
+        let blue: Rc<dyn Material<f64>> = Rc::new(Lambertian::new(Color::new(0.0, 0.0, 1.0)));
+        let mut world = HittableList::new();
+        for (x, material) in [(0.0, &blue), (3.0, &red), (6.0, &blue)] {
+            world.add(Rc::new(Sphere::new(
+                Point::new(x, 0.0, 0.0),
+                1.0,
+                material.clone(),
+            )));
+        }
+
+        let ids: Vec<_> = [0.0, 3.0, 6.0]
+            .iter()
+            .map(|&x| {
+                let ray = Ray::new(Point::new(x, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+                let rec = world
+                    .hit(&ray, Interval::new(0.001, f64::INFINITY))
+                    .unwrap();
+                (rec.object_id, rec.material_id)
+            })
+            .collect();
+        assert_eq!(ids, [(0, Some(0)), (1, Some(1)), (2, Some(0))]);
+    }
 }
@@ -0,0 +1,301 @@
+use crate::{
+    dither::Dither,
+    tile::Region,
+    utils::{luminance, quantize, Color},
+};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+/// A plain linear framebuffer, row major from the top left.
+#[derive(Debug, Clone)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color<f64>>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Image {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::splat(0.0); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color<f64> {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Color<f64>) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /// Copies out the pixels in `region`, which has to lie inside the image.
+    pub fn crop(&self, region: Region) -> Image {
+        let mut cropped = Image::new(region.width, region.height);
+        for (i, j) in region.pixels() {
+            cropped.set(i - region.x, j - region.y, self.get(i, j));
+        }
+        cropped
+    }
+
+    pub fn map(mut self, f: impl Fn(Color<f64>) -> Color<f64>) -> Image {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = f(*pixel);
+        }
+        self
+    }
+
+    /// Writes the values as they are, clamped to [0, 1] and quantized with
+    /// `dither`. Anything that needs tone mapping or gamma has to be applied
+    /// beforehand. `PnmFormat::Float` is the exception and keeps the values
+    /// unclamped.
+    pub fn write_pnm(
+        &self,
+        out: &mut impl Write,
+        format: PnmFormat,
+        dither: Dither,
+    ) -> io::Result<()> {
+        if format == PnmFormat::Float {
+            return self.write_pfm(out);
+        }
+
+        let max = format.max_value();
+        writeln!(
+            out,
+            "{}\n{} {}\n{}",
+            format.magic(),
+            self.width,
+            self.height,
+            max
+        )?;
+
+        let mut row = Vec::with_capacity(self.width * 3 * 2);
+        for y in 0..self.height {
+            row.clear();
+            for x in 0..self.width {
+                let color = self.get(x, y);
+                let offset = dither.offset(x, y);
+                let (gray, rgb) = ([luminance(color)], color.to_array());
+                let values: &[f64] = if format.is_gray() { &gray } else { &rgb };
+                for &value in values {
+                    let level = quantize(value, max, offset);
+                    match format {
+                        PnmFormat::Ascii => {
+                            row.extend_from_slice(level.to_string().as_bytes());
+                            row.push(b' ');
+                        }
+                        _ if max > 255 => row.extend_from_slice(&(level as u16).to_be_bytes()),
+                        _ => row.push(level as u8),
+                    }
+                }
+                if format == PnmFormat::Ascii {
+                    *row.last_mut().unwrap() = b'\n';
+                }
+            }
+            out.write_all(&row)?;
+        }
+        Ok(())
+    }
+
+    /// Portable float map: little endian f32 RGB, bottom row first.
+    fn write_pfm(&self, out: &mut impl Write) -> io::Result<()> {
+        // a negative scale means little endian
+        writeln!(out, "PF\n{} {}\n-1.0", self.width, self.height)?;
+        let mut row = Vec::with_capacity(self.width * 3 * 4);
+        for y in (0..self.height).rev() {
+            row.clear();
+            for x in 0..self.width {
+                for value in self.get(x, y).to_array() {
+                    row.extend_from_slice(&(value as f32).to_le_bytes());
+                }
+            }
+            out.write_all(&row)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a Netpbm image (P2, P3, P5 or P6, 8 or 16 bits), scaled so the
+    /// format's maximum is 1. The values are taken as they are, with no
+    /// gamma removed.
+    pub fn read_pnm(input: &mut impl BufRead) -> io::Result<Image> {
+        let magic = read_token(input)?;
+        let (channels, ascii) = match magic.as_str() {
+            "P2" => (1, true),
+            "P3" => (3, true),
+            "P5" => (1, false),
+            "P6" => (3, false),
+            _ => return Err(invalid_pnm("not a Netpbm image")),
+        };
+        let mut number = || -> io::Result<usize> {
+            read_token(input)?
+                .parse()
+                .map_err(|_| invalid_pnm("bad number in the Netpbm header"))
+        };
+        let (width, height, max) = (number()?, number()?, number()?);
+        if max == 0 || max > 65535 {
+            return Err(invalid_pnm("Netpbm maximum out of range"));
+        }
+
+        let count = width * height * channels;
+        let values: Vec<usize> = if ascii {
+            (0..count).map(|_| number()).collect::<io::Result<_>>()?
+        } else {
+            let size = if max > 255 { 2 } else { 1 };
+            let mut bytes = vec![0; count * size];
+            input.read_exact(&mut bytes)?;
+            bytes
+                .chunks(size)
+                .map(|c| c.iter().fold(0, |v, &b| v << 8 | b as usize))
+                .collect()
+        };
+
+        let mut image = Image::new(width, height);
+        for (pixel, value) in image.pixels.iter_mut().zip(values.chunks(channels)) {
+            let channel = |i: usize| value[i.min(channels - 1)] as f64 / max as f64;
+            *pixel = Color::new(channel(0), channel(1), channel(2));
+        }
+        Ok(image)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Image> {
+        Self::read_pnm(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn save(
+        &self,
+        path: impl AsRef<Path>,
+        format: PnmFormat,
+        dither: Dither,
+    ) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_pnm(&mut out, format, dither)?;
+        out.flush()
+    }
+}
+
+fn invalid_pnm(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The next whitespace separated token of a Netpbm header, skipping
+/// comments. Consumes the single whitespace byte after it, so binary data
+/// starts right after the header.
+fn read_token(input: &mut impl BufRead) -> io::Result<String> {
+    let mut token = String::new();
+    let mut byte = [0];
+    loop {
+        if input.read(&mut byte)? == 0 {
+            if token.is_empty() {
+                return Err(invalid_pnm("Netpbm data ends early"));
+            }
+            return Ok(token);
+        }
+        match byte[0] {
+            b'#' if token.is_empty() => {
+                input.read_until(b'\n', &mut Vec::new())?;
+            }
+            b if b.is_ascii_whitespace() => {
+                if !token.is_empty() {
+                    return Ok(token);
+                }
+            }
+            b => token.push(b as char),
+        }
+    }
+}
+
+/// Netpbm flavours an `Image` can be written as.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum PnmFormat {
+    /// Plain text RGB, 8 bits per channel (P3).
+    Ascii,
+    /// Binary RGB, 8 bits per channel (P6).
+    #[default]
+    Binary,
+    /// Binary RGB, 16 bits per channel big endian (P6).
+    Binary16,
+    /// Binary grayscale of the luma, 8 bits (P5).
+    Gray,
+    /// Binary grayscale of the luma, 16 bits big endian (P5).
+    Gray16,
+    /// Linear RGB as 32 bit floats (PF), for HDR output like environment
+    /// maps. Not part of Netpbm proper but read by most HDR tools.
+    Float,
+}
+
+impl PnmFormat {
+    fn magic(&self) -> &'static str {
+        match self {
+            PnmFormat::Ascii => "P3",
+            PnmFormat::Binary | PnmFormat::Binary16 => "P6",
+            PnmFormat::Gray | PnmFormat::Gray16 => "P5",
+            PnmFormat::Float => "PF",
+        }
+    }
+
+    fn max_value(&self) -> u32 {
+        match self {
+            PnmFormat::Binary16 | PnmFormat::Gray16 => 65535,
+            _ => 255,
+        }
+    }
+
+    fn is_gray(&self) -> bool {
+        matches!(self, PnmFormat::Gray | PnmFormat::Gray16)
+    }
+
+    /// Whether values above 1 survive, so there's no point tone mapping.
+    pub fn is_hdr(&self) -> bool {
+        *self == PnmFormat::Float
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PnmFormat::Gray | PnmFormat::Gray16 => "pgm",
+            PnmFormat::Float => "pfm",
+            _ => "ppm",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn netpbm_reads_back_what_was_written() {
+        let mut image = Image::new(3, 2);
+        image.set(0, 0, Color::new(1.0, 0.0, 0.0));
+        image.set(2, 1, Color::new(0.0, 0.2, 1.0));
+        for format in [PnmFormat::Ascii, PnmFormat::Binary, PnmFormat::Binary16] {
+            let mut bytes = Vec::new();
+            image.write_pnm(&mut bytes, format, Dither::None).unwrap();
+            let read = Image::read_pnm(&mut bytes.as_slice()).unwrap();
+            assert_eq!((read.width(), read.height()), (3, 2));
+            assert_eq!(read.get(0, 0).to_array(), [1.0, 0.0, 0.0]);
+            let max = format.max_value() as f64;
+            assert_eq!(
+                read.get(2, 1).to_array(),
+                [0.0, (0.2 * max).round() / max, 1.0]
+            );
+        }
+
+        let commented = b"P2\n# a comment\n2 1\n4\n0 4\n";
+        let read = Image::read_pnm(&mut commented.as_slice()).unwrap();
+        assert_eq!(read.get(1, 0).to_array(), [1.0; 3]);
+        assert!(Image::read_pnm(&mut b"P6\n2 2\n255\n".as_slice()).is_err());
+    }
+}
@@ -0,0 +1,627 @@
+use crate::{
+    hit::{HitRecord, Hittable, HittableList},
+    sampler::{sample_unit_vector, Sampler},
+    spectrum::Wavelengths,
+    utils::{Color, Float, Interval, Ray, Vec3},
+};
+use std::fmt;
+
+/// Estimates the light arriving back along a camera ray.
+pub trait Integrator<T> {
+    fn radiance(&self, world: &dyn Hittable<T>, ray: Ray<T>, sampler: &mut dyn Sampler)
+        -> Color<T>;
+}
+
+impl<T> fmt::Debug for dyn Integrator<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Integrator")
+    }
+}
+
+fn ray_interval<T: Float>() -> Interval<T> {
+    Interval::new(T::from(0.000000001).unwrap(), T::infinity())
+}
+
+fn black<T: Float>() -> Color<T> {
+    Color::splat(T::zero())
+}
+
+/// The sky gradient every ray that escapes the scene picks up.
+pub fn background<T: Float>(ray: &Ray<T>) -> Color<T> {
+    let unit_direction = ray.get_direction().unit_vector();
+    let a = (unit_direction.y + T::one()) * T::from(0.5).unwrap();
+    Color::splat(T::one()) * (T::one() - a)
+        + Color::new(
+            T::from(0.5).unwrap(),
+            T::from(0.7).unwrap(),
+            T::from(1.0).unwrap(),
+        ) * a
+}
+
+/// The absorbing media a path is inside of, innermost last, so every
+/// stretch through one loses light, whatever it ends on.
+#[derive(Debug, Clone)]
+struct Media<T> {
+    stack: Vec<Option<Color<T>>>,
+}
+
+impl<T: Float> Media<T> {
+    fn new() -> Self {
+        Self { stack: vec![] }
+    }
+
+    /// Absorption around a ray leaving the hit in `record` along `direction`.
+    fn around(&self, record: &HitRecord<T>, direction: &Vec3<T>) -> Option<Color<T>> {
+        // the normal faces the side the incoming ray was on
+        if direction.dot(&record.normal) >= T::zero() {
+            self.stack.last().copied().flatten()
+        } else if record.front_face() {
+            record.material.absorption()
+        } else {
+            let outer = self.stack.len().checked_sub(2);
+            outer.and_then(|i| self.stack[i])
+        }
+    }
+
+    /// Follows `scattered` into or out of the object hit in `record`.
+    fn cross(&mut self, record: &HitRecord<T>, scattered: &Ray<T>) {
+        if scattered.get_direction().dot(&record.normal) >= T::zero() {
+            return;
+        }
+        if record.front_face() {
+            self.stack.push(record.material.absorption());
+        } else {
+            self.stack.pop();
+        }
+    }
+
+    /// Light left after `distance` through the innermost medium.
+    fn transmittance(&self, distance: T) -> Color<T> {
+        transmittance(self.stack.last().copied().flatten(), distance)
+    }
+}
+
+/// Beer-Lambert falloff over `distance`. Channels that don't absorb stay
+/// whole even for rays that never hit anything.
+fn transmittance<T: Float>(absorption: Option<Color<T>>, distance: T) -> Color<T> {
+    match absorption {
+        Some(absorption) => absorption.map(|a| match a > T::zero() {
+            true => (-a * distance).exp(),
+            false => T::one(),
+        }),
+        None => Color::splat(T::one()),
+    }
+}
+
+/// Length along `ray` to the hit at `t`.
+fn distance<T: Float>(ray: &Ray<T>, t: T) -> T {
+    t * ray.get_direction().length()
+}
+
+/// What one shadow ray found, kept apart so spectral paths can upsample
+/// each color on its own.
+struct LightSample<T> {
+    emitted: Color<T>,
+    /// The BSDF times the cosine, over the light's pdf.
+    bsdf: Color<T>,
+    /// What the medium around the shadow ray let through.
+    absorbed: Color<T>,
+}
+
+impl<T: Float> LightSample<T> {
+    fn radiance(&self) -> Color<T> {
+        self.emitted.mul_vec3(self.bsdf).mul_vec3(self.absorbed)
+    }
+}
+
+/// One light sample towards `lights` from the hit in `record`, for the part
+/// of the material's BSDF that isn't a delta lobe. `None` for perfectly
+/// specular materials, or when the light is blocked.
+fn sample_lights<T: Float>(
+    world: &dyn Hittable<T>,
+    lights: &HittableList<T>,
+    ray_in: &Ray<T>,
+    record: &HitRecord<T>,
+    media: &Media<T>,
+    sampler: &mut dyn Sampler,
+) -> Option<LightSample<T>> {
+    if lights.is_empty() {
+        return None;
+    }
+
+    let direction = lights.random(&record.point, sampler);
+    let light_pdf = lights.pdf_value(&record.point, &direction);
+    if light_pdf <= T::zero() {
+        return None;
+    }
+
+    let shadow_ray = Ray::new(record.point, direction);
+    let bsdf = record.material.eval(ray_in, record, &shadow_ray)?;
+    let light_rec = world.hit(&shadow_ray, ray_interval())?;
+    let emitted = light_rec.material.emitted(&shadow_ray, &light_rec)?;
+
+    let absorbed = transmittance(
+        media.around(record, &direction),
+        distance(&shadow_ray, light_rec.t),
+    );
+    Some(LightSample {
+        emitted,
+        bsdf: bsdf / light_pdf,
+        absorbed,
+    })
+}
+
+/// Plain path tracing: follows whatever direction each material scatters in.
+#[derive(Debug)]
+pub struct PathTracer {
+    max_depth: i32,
+}
+
+impl PathTracer {
+    pub fn new(max_depth: i32) -> PathTracer {
+        Self { max_depth }
+    }
+}
+
+impl<T> Integrator<T> for PathTracer
+where
+    T: Float,
+{
+    fn radiance(
+        &self,
+        world: &dyn Hittable<T>,
+        ray: Ray<T>,
+        sampler: &mut dyn Sampler,
+    ) -> Color<T> {
+        let mut ray = ray;
+        let mut throughput = Color::splat(T::one());
+        let mut radiance = black();
+        let mut media = Media::new();
+
+        for depth in 0..self.max_depth.max(0) as u32 {
+            sampler.start_bounce(depth);
+            let Some(rec) = world.hit(&ray, ray_interval()) else {
+                let absorbed = media.transmittance(T::infinity());
+                radiance = radiance + throughput.mul_vec3(background(&ray)).mul_vec3(absorbed);
+                break;
+            };
+            throughput = throughput.mul_vec3(media.transmittance(distance(&ray, rec.t)));
+            if let Some(emitted) = rec.material.emitted(&ray, &rec) {
+                radiance = radiance + throughput.mul_vec3(emitted);
+            }
+            // absorbed rays carry no more light back to the camera
+            let Some((attenuation, scattered)) = rec.material.scatter(&ray, &rec, sampler) else {
+                break;
+            };
+            media.cross(&rec, &scattered);
+            throughput = throughput.mul_vec3(attenuation);
+            ray = scattered;
+        }
+
+        radiance
+    }
+}
+
+/// How a path carries light: plain RGB, or values at a few wavelengths.
+trait Channels<T> {
+    type Scalar: Float;
+
+    /// `color` in these channels.
+    fn lift(&self, color: Color<T>) -> Vec3<Self::Scalar>;
+
+    /// Tags a ray with what it carries.
+    fn carry(&self, ray: Ray<T>) -> Ray<T> {
+        ray
+    }
+
+    /// Picks up what `scattered` goes on with.
+    fn follow(&mut self, _scattered: &Ray<T>) {}
+
+    /// Keeps only what the hero wavelength carries, if there's more.
+    fn keep_hero(&mut self) {}
+
+    fn to_rgb(&self, values: Vec3<Self::Scalar>) -> Color<T>;
+}
+
+struct Rgb;
+
+impl<T: Float> Channels<T> for Rgb {
+    type Scalar = T;
+
+    fn lift(&self, color: Color<T>) -> Color<T> {
+        color
+    }
+
+    fn to_rgb(&self, values: Color<T>) -> Color<T> {
+        values
+    }
+}
+
+impl<T: Float> Channels<T> for Wavelengths {
+    type Scalar = f64;
+
+    fn lift(&self, color: Color<T>) -> Vec3<f64> {
+        self.upsample(color.map(|c| c.to_f64().unwrap_or(0.0)))
+    }
+
+    fn carry(&self, ray: Ray<T>) -> Ray<T> {
+        ray.with_wavelengths(Some(*self))
+    }
+
+    fn follow(&mut self, scattered: &Ray<T>) {
+        // dispersive materials hand back fewer wavelengths than they got
+        *self = scattered.wavelengths().unwrap_or(*self);
+    }
+
+    fn keep_hero(&mut self) {
+        *self = self.terminate_secondary();
+    }
+
+    fn to_rgb(&self, values: Vec3<f64>) -> Color<T> {
+        Wavelengths::to_rgb(self, values).map(|c| T::from(c).unwrap_or(T::zero()))
+    }
+}
+
+/// The path tracing loop with next event estimation both `NeePathTracer`
+/// and `SpectralPathTracer` run, in whichever channels they carry.
+fn trace_nee<T: Float, C: Channels<T>>(
+    world: &dyn Hittable<T>,
+    lights: &HittableList<T>,
+    max_depth: i32,
+    ray: Ray<T>,
+    mut channels: C,
+    sampler: &mut dyn Sampler,
+) -> Color<T> {
+    let mut ray = channels.carry(ray);
+    let mut throughput = channels.lift(Color::splat(T::one()));
+    let mut radiance = channels.lift(black());
+    let mut specular_bounce = true;
+    let mut media = Media::new();
+
+    for depth in 0..max_depth.max(0) as u32 {
+        sampler.start_bounce(depth);
+        let Some(rec) = world.hit(&ray, ray_interval()) else {
+            let sky = background(&ray).mul_vec3(media.transmittance(T::infinity()));
+            radiance = radiance + throughput.mul_vec3(channels.lift(sky));
+            break;
+        };
+        let absorbed = media.transmittance(distance(&ray, rec.t));
+        throughput = throughput.mul_vec3(channels.lift(absorbed));
+
+        // lights we sample explicitly were already counted by the previous
+        // bounce's shadow ray
+        if let Some(emitted) = rec.material.emitted(&ray, &rec) {
+            let sampled = !specular_bounce
+                && lights.pdf_value(ray.get_origin(), ray.get_direction()) > T::zero();
+            if !sampled {
+                radiance = radiance + throughput.mul_vec3(channels.lift(emitted));
+            }
+        }
+
+        // what the material says at the hero wavelength can't stand in for
+        // the others, not even for the light sample
+        if rec.material.wavelength_selective() {
+            channels.keep_hero();
+            ray = channels.carry(ray);
+        }
+
+        // the light sample doesn't depend on what scatter picked, so it goes
+        // in even when the path ends here
+        let scattered = rec.material.scatter(&ray, &rec, sampler);
+        sampler.start_light_sample(depth);
+        if let Some(light) = sample_lights(world, lights, &ray, &rec, &media, sampler) {
+            let direct = channels
+                .lift(light.emitted)
+                .mul_vec3(channels.lift(light.bsdf))
+                .mul_vec3(channels.lift(light.absorbed));
+            radiance = radiance + throughput.mul_vec3(direct);
+        }
+
+        let Some((attenuation, scattered)) = scattered else {
+            break;
+        };
+        specular_bounce = rec
+            .material
+            .scattering_pdf(&ray, &rec, &scattered)
+            .is_none();
+
+        media.cross(&rec, &scattered);
+        channels.follow(&scattered);
+        throughput = throughput.mul_vec3(channels.lift(attenuation));
+        ray = channels.carry(scattered);
+    }
+
+    channels.to_rgb(radiance)
+}
+
+/// Path tracing with next event estimation: every bounce also takes a
+/// shadow ray towards `lights`, whichever lobe the material picked, for the
+/// part of its BSDF that isn't a delta lobe. Light found by bouncing is then
+/// only counted after a delta lobe.
+pub struct NeePathTracer<T> {
+    max_depth: i32,
+    lights: HittableList<T>,
+}
+
+impl<T> NeePathTracer<T> {
+    pub fn new(max_depth: i32, lights: HittableList<T>) -> NeePathTracer<T> {
+        Self { max_depth, lights }
+    }
+}
+
+impl<T> Integrator<T> for NeePathTracer<T>
+where
+    T: Float,
+{
+    fn radiance(
+        &self,
+        world: &dyn Hittable<T>,
+        ray: Ray<T>,
+        sampler: &mut dyn Sampler,
+    ) -> Color<T> {
+        trace_nee(world, &self.lights, self.max_depth, ray, Rgb, sampler)
+    }
+}
+
+/// Path tracing with next event estimation, one set of wavelengths per path
+/// instead of RGB. Material and light colors are upsampled to spectra on
+/// the way, each on its own before they're multiplied, and the result goes
+/// back to RGB through CIE XYZ, so dispersive glass splits light into its
+/// colors.
+pub struct SpectralPathTracer<T> {
+    max_depth: i32,
+    lights: HittableList<T>,
+}
+
+impl<T> SpectralPathTracer<T> {
+    pub fn new(max_depth: i32, lights: HittableList<T>) -> SpectralPathTracer<T> {
+        Self { max_depth, lights }
+    }
+}
+
+impl<T> Integrator<T> for SpectralPathTracer<T>
+where
+    T: Float,
+{
+    fn radiance(
+        &self,
+        world: &dyn Hittable<T>,
+        ray: Ray<T>,
+        sampler: &mut dyn Sampler,
+    ) -> Color<T> {
+        let wavelengths = Wavelengths::sample(sampler.get_1d());
+        trace_nee(
+            world,
+            &self.lights,
+            self.max_depth,
+            ray,
+            wavelengths,
+            sampler,
+        )
+    }
+}
+
+/// Fraction of the hemisphere above the first hit that is unoccluded within
+/// `distance`. Escaping rays count as fully open.
+#[derive(Debug)]
+pub struct AmbientOcclusion<T> {
+    distance: T,
+}
+
+impl<T> AmbientOcclusion<T> {
+    pub fn new(distance: T) -> AmbientOcclusion<T> {
+        Self { distance }
+    }
+}
+
+impl<T> Integrator<T> for AmbientOcclusion<T>
+where
+    T: Float + From<f64>,
+    Vec3<T>: From<Vec3<f64>>,
+{
+    fn radiance(
+        &self,
+        world: &dyn Hittable<T>,
+        ray: Ray<T>,
+        sampler: &mut dyn Sampler,
+    ) -> Color<T> {
+        let Some(rec) = world.hit(&ray, ray_interval()) else {
+            return Color::splat(T::one());
+        };
+
+        // cosine weighted, same as a lambertian bounce
+        sampler.start_bounce(0);
+        let mut direction = rec.normal + sample_unit_vector(sampler.get_2d()).map(Into::into);
+        if direction.near_zero() {
+            direction = rec.normal;
+        }
+
+        let occlusion_ray = Ray::new(rec.point, direction.unit_vector());
+        let interval = Interval::new(ray_interval().min, self.distance);
+        match world.hit(&occlusion_ray, interval) {
+            Some(_) => black(),
+            None => Color::splat(T::one()),
+        }
+    }
+}
+
+/// Whitted style recursive tracer for quick previews: specular surfaces are
+/// followed recursively, everything else is shaded with one shadow ray
+/// towards `lights` plus a constant ambient term.
+pub struct Whitted<T> {
+    max_depth: i32,
+    lights: HittableList<T>,
+    ambient: Color<T>,
+}
+
+impl<T> Whitted<T> {
+    pub fn new(max_depth: i32, lights: HittableList<T>, ambient: Color<T>) -> Whitted<T> {
+        Self {
+            max_depth,
+            lights,
+            ambient,
+        }
+    }
+}
+
+impl<T> Whitted<T>
+where
+    T: Float,
+{
+    fn trace(
+        &self,
+        world: &dyn Hittable<T>,
+        ray: Ray<T>,
+        depth: i32,
+        media: &Media<T>,
+        sampler: &mut dyn Sampler,
+    ) -> Color<T> {
+        if depth <= 0 {
+            return black();
+        }
+        let Some(rec) = world.hit(&ray, ray_interval()) else {
+            return background(&ray).mul_vec3(media.transmittance(T::infinity()));
+        };
+        let absorbed = media.transmittance(distance(&ray, rec.t));
+
+        let bounce = (self.max_depth - depth) as u32;
+        sampler.start_bounce(bounce);
+        let emitted = rec.material.emitted(&ray, &rec).unwrap_or(black());
+        let Some((attenuation, scattered)) = rec.material.scatter(&ray, &rec, sampler) else {
+            return emitted.mul_vec3(absorbed);
+        };
+
+        let reflected = match rec.material.scattering_pdf(&ray, &rec, &scattered) {
+            Some(_) => {
+                sampler.start_light_sample(bounce);
+                let direct = sample_lights(world, &self.lights, &ray, &rec, media, sampler);
+                attenuation.mul_vec3(self.ambient)
+                    + direct.map_or(black(), |light| light.radiance())
+            }
+            None => {
+                let mut media = media.clone();
+                media.cross(&rec, &scattered);
+                let traced = self.trace(world, scattered, depth - 1, &media, sampler);
+                attenuation.mul_vec3(traced)
+            }
+        };
+        (emitted + reflected).mul_vec3(absorbed)
+    }
+}
+
+impl<T> Integrator<T> for Whitted<T>
+where
+    T: Float,
+{
+    fn radiance(
+        &self,
+        world: &dyn Hittable<T>,
+        ray: Ray<T>,
+        sampler: &mut dyn Sampler,
+    ) -> Color<T> {
+        self.trace(world, ray, self.max_depth, &Media::new(), sampler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        material::{Conductor, Dielectric, DiffuseLight, Material, Principled},
+        sampler::SamplerKind,
+        sphere::Sphere,
+        thin_film::ThinFilm,
+        utils::{Point, Rc},
+    };
+
+    /// Average radiance straight down onto the middle of a big ball made of
+    /// `floor`, with a light hanging over it.
+    fn estimate(
+        integrator: &dyn Integrator<f64>,
+        floor: Rc<dyn Material<f64>>,
+        light: Rc<Sphere<f64>>,
+        samples: u32,
+    ) -> f64 {
+        let mut world = HittableList::new();
+        world.add(Rc::new(Sphere::new(
+            Point::new(0.0, -100.0, 0.0),
+            100.0,
+            floor,
+        )));
+        world.add(light);
+
+        let mut sampler = SamplerKind::Independent.build(samples, 1);
+        let mut sum = 0.0;
+        for i in 0..samples {
+            sampler.start_pixel_sample((0, 0), i);
+            let ray = Ray::new(Point::new(0.3, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+            sum += integrator.radiance(&world, ray, sampler.as_mut()).x;
+        }
+        sum / samples as f64
+    }
+
+    #[test]
+    fn nee_agrees_with_path_tracing_on_smooth_principled() {
+        let light = Rc::new(Sphere::new(
+            Point::new(-1.0, 2.0, 0.0),
+            0.5,
+            Rc::new(DiffuseLight::new(Color::splat(20.0))),
+        ));
+        let mut lights = HittableList::new();
+        lights.add(light.clone());
+
+        let floor = Rc::new(Principled::new(Color::new(0.8, 0.5, 0.3)).with_roughness(0.0));
+        let reference = estimate(&PathTracer::new(4), floor.clone(), light.clone(), 200_000);
+        let nee = estimate(&NeePathTracer::new(4, lights), floor, light, 20_000);
+        assert!((nee / reference - 1.0).abs() < 0.03, "{nee} vs {reference}");
+    }
+
+    #[test]
+    fn spectral_nee_agrees_with_path_tracing_on_filmed_conductor() {
+        let light = Rc::new(Sphere::new(
+            Point::new(-1.0, 2.0, 0.0),
+            0.5,
+            Rc::new(DiffuseLight::new(Color::splat(20.0))),
+        ));
+        let mut lights = HittableList::new();
+        lights.add(light.clone());
+
+        // rough enough that scatter often goes below the surface and ends
+        // the path right after the light sample
+        let floor = Rc::new(Conductor::gold(0.6).with_thin_film(ThinFilm::new(300.0, 1.8)));
+        let no_lights = SpectralPathTracer::new(4, HittableList::new());
+        let reference = estimate(&no_lights, floor.clone(), light.clone(), 400_000);
+        let nee = estimate(&SpectralPathTracer::new(4, lights), floor, light, 40_000);
+        assert!((nee / reference - 1.0).abs() < 0.03, "{nee} vs {reference}");
+    }
+
+    #[test]
+    fn tinted_glass_absorbs_on_the_way_to_what_it_holds() {
+        // index 1 so nothing reflects, and half the radius is inside the glass
+        let glass = Dielectric::new(1.0).with_absorption(Color::splat(1.0));
+        let mut world = HittableList::new();
+        world.add(Rc::new(Sphere::new(Point::splat(0.0), 1.0, Rc::new(glass))));
+        world.add(Rc::new(Sphere::new(
+            Point::splat(0.0),
+            0.5,
+            Rc::new(DiffuseLight::new(Color::splat(1.0))),
+        )));
+
+        let integrators: [&dyn Integrator<f64>; 3] = [
+            &PathTracer::new(10),
+            &NeePathTracer::new(10, HittableList::new()),
+            &Whitted::new(10, HittableList::new(), Color::splat(0.0)),
+        ];
+        for integrator in integrators {
+            let mut sampler = SamplerKind::Independent.build(1, 1);
+            sampler.start_pixel_sample((0, 0), 0);
+            let ray = Ray::new(Point::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+            let radiance = integrator.radiance(&world, ray, sampler.as_mut());
+            assert!(
+                (radiance.x - (-0.5_f64).exp()).abs() < 1e-9,
+                "{}",
+                radiance.x
+            );
+        }
+    }
+}
@@ -0,0 +1,475 @@
+use crate::{
+    image::Image,
+    sampler::{rehash_2d, sample_unit_disk},
+    utils::{luminance, Ray, Vec3, PI},
+};
+use std::{io, path::Path};
+
+/// The camera's optics. By default an ideal thin lens with a round
+/// aperture, sized by the camera's `defocus_angle`.
+#[derive(Debug, Clone, Default)]
+pub struct Lens {
+    pub aperture: Aperture,
+    /// How far the lens barrel's opening slides towards the edges of the
+    /// image, in aperture radii at the edge of the frame. It cuts off part of
+    /// the aperture there, so bokeh near the corners turns into a cat's eye.
+    /// Only applies to the thin lens.
+    pub cats_eye: f64,
+    /// Trace through real lens elements instead of the thin lens.
+    pub system: Option<LensSystem>,
+}
+
+impl Lens {
+    pub fn new(aperture: Aperture) -> Lens {
+        Self {
+            aperture,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_cats_eye(mut self, cats_eye: f64) -> Self {
+        self.cats_eye = cats_eye.max(0.0);
+        self
+    }
+
+    pub fn with_system(mut self, system: LensSystem) -> Self {
+        self.system = Some(system);
+        self
+    }
+
+    /// Point on the thin lens' aperture, in units of the aperture radius.
+    /// `screen` is where on the image the ray is for, from -1 to 1 across
+    /// the shorter side with y up. Points the barrel blocks are drawn again,
+    /// so only the bokeh changes shape, not the exposure. `None` if the
+    /// barrel leaves next to nothing open.
+    pub fn sample(&self, u: (f64, f64), screen: (f64, f64)) -> Option<Vec3<f64>> {
+        const ATTEMPTS: u32 = 16;
+
+        let (cx, cy) = (screen.0 * self.cats_eye, screen.1 * self.cats_eye);
+        (0..ATTEMPTS)
+            .map(|attempt| match attempt {
+                0 => self.aperture.sample(u),
+                _ => self.aperture.sample(rehash_2d(u, attempt)),
+            })
+            .find(|p| (p.x - cx).powi(2) + (p.y - cy).powi(2) <= 1.0)
+    }
+}
+
+/// Shape of the opening light passes through, which is the shape out of
+/// focus highlights take.
+#[derive(Debug, Clone, Default)]
+pub enum Aperture {
+    #[default]
+    Circle,
+    /// A regular polygon with `blades` sides inscribed in the circle, turned
+    /// by `rotation` degrees.
+    Polygon { blades: u32, rotation: f64 },
+    /// Any shape, painted in an image.
+    Image(ApertureImage),
+}
+
+impl Aperture {
+    /// Point distributed by how much light gets through, within the unit
+    /// disk (the unit square for images).
+    pub fn sample(&self, u: (f64, f64)) -> Vec3<f64> {
+        match self {
+            Aperture::Circle => sample_unit_disk(u),
+            Aperture::Polygon { blades, rotation } => {
+                // pick a triangle between the middle and an edge, then a
+                // point in it
+                let blades = (*blades).max(3);
+                let scaled = u.0 * blades as f64;
+                let blade = (scaled as u32).min(blades - 1);
+                let (u1, u2) = (scaled - blade as f64, u.1);
+                let a = polygon_corner(blade, blades, *rotation);
+                let b = polygon_corner(blade + 1, blades, *rotation);
+                let s = u1.sqrt();
+                a * (s * (1.0 - u2)) + b * (s * u2)
+            }
+            Aperture::Image(image) => image.sample(u),
+        }
+    }
+
+    /// Whether light gets through at `p`, in the same units as `sample`.
+    pub fn contains(&self, p: Vec3<f64>) -> bool {
+        match self {
+            Aperture::Circle => p.x * p.x + p.y * p.y <= 1.0,
+            Aperture::Polygon { blades, rotation } => {
+                // the distance to the nearest edge, along the middle of its
+                // sector, can't be more than the apothem
+                let blades = (*blades).max(3);
+                let sector = 2.0 * PI / blades as f64;
+                let angle =
+                    (p.y.atan2(p.x) - rotation.to_radians() - PI / 2.0).rem_euclid(2.0 * PI);
+                let middle = ((angle / sector).floor() + 0.5) * sector;
+                let distance = p.x.hypot(p.y) * (angle - middle).cos();
+                distance <= (sector / 2.0).cos()
+            }
+            Aperture::Image(image) => image.contains(p),
+        }
+    }
+}
+
+/// Corner `i` of a regular polygon, the first one at the top before
+/// rotating.
+fn polygon_corner(i: u32, corners: u32, rotation: f64) -> Vec3<f64> {
+    let angle = PI / 2.0 + rotation.to_radians() + 2.0 * PI * i as f64 / corners as f64;
+    Vec3::new(angle.cos(), angle.sin(), 0.0)
+}
+
+/// An aperture mask. Brighter pixels let more light through; the image is
+/// stretched over the square around the aperture circle.
+#[derive(Debug, Clone)]
+pub struct ApertureImage {
+    width: usize,
+    height: usize,
+    weights: Vec<f64>,
+    /// Cumulative sums of the row totals, normalized, starting at 0.
+    row_cdf: Vec<f64>,
+    /// The same within every row, `width + 1` entries each.
+    column_cdfs: Vec<f64>,
+}
+
+impl ApertureImage {
+    /// `None` if the image is black everywhere, so no light would get through.
+    pub fn new(image: &Image) -> Option<ApertureImage> {
+        let (width, height) = (image.width(), image.height());
+        let mut weights = Vec::with_capacity(width * height);
+        let mut row_cdf = vec![0.0];
+        let mut column_cdfs = Vec::with_capacity((width + 1) * height);
+        for y in 0..height {
+            let start = column_cdfs.len();
+            column_cdfs.push(0.0);
+            let mut total = 0.0;
+            for x in 0..width {
+                let weight = luminance(image.get(x, y)).max(0.0);
+                weights.push(weight);
+                total += weight;
+                column_cdfs.push(total);
+            }
+            if total > 0.0 {
+                column_cdfs[start..].iter_mut().for_each(|c| *c /= total);
+            }
+            row_cdf.push(row_cdf[y] + total);
+        }
+
+        let total = *row_cdf.last()?;
+        if total <= 0.0 {
+            return None;
+        }
+        row_cdf.iter_mut().for_each(|c| *c /= total);
+        Some(Self {
+            width,
+            height,
+            weights,
+            row_cdf,
+            column_cdfs,
+        })
+    }
+
+    /// Reads the mask from a Netpbm file.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<ApertureImage> {
+        Self::new(&Image::load(path)?).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "aperture image is black everywhere",
+            )
+        })
+    }
+
+    fn sample(&self, u: (f64, f64)) -> Vec3<f64> {
+        let (row, fy) = sample_cdf(&self.row_cdf, u.1);
+        let columns = &self.column_cdfs[row * (self.width + 1)..(row + 1) * (self.width + 1)];
+        let (column, fx) = sample_cdf(columns, u.0);
+        let x = (column as f64 + fx) / self.width as f64;
+        let y = (row as f64 + fy) / self.height as f64;
+        Vec3::new(2.0 * x - 1.0, 1.0 - 2.0 * y, 0.0)
+    }
+
+    fn contains(&self, p: Vec3<f64>) -> bool {
+        let x = (p.x + 1.0) / 2.0 * self.width as f64;
+        let y = (1.0 - p.y) / 2.0 * self.height as f64;
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return false;
+        }
+        self.weights[y as usize * self.width + x as usize] > 0.0
+    }
+}
+
+/// Which bucket `u` falls in, and how far along it.
+fn sample_cdf(cdf: &[f64], u: f64) -> (usize, f64) {
+    // skip empty buckets by taking the last one starting at or below u
+    let bucket = cdf[1..].partition_point(|&c| c <= u).min(cdf.len() - 2);
+    let width = cdf[bucket + 1] - cdf[bucket];
+    let offset = if width > 0.0 {
+        (u - cdf[bucket]) / width
+    } else {
+        0.5
+    };
+    (bucket, offset.clamp(0.0, 1.0))
+}
+
+/// One surface of a lens prescription, listed from the front of the lens to
+/// the back like published prescriptions are.
+#[derive(Debug, Copy, Clone)]
+pub struct LensElement {
+    /// Radius of curvature, positive when the centre is towards the film. 0
+    /// marks the aperture stop, which is flat.
+    pub radius: f64,
+    /// Distance along the axis to the next surface, or to the film for the
+    /// last one.
+    pub thickness: f64,
+    /// Index of refraction behind the surface, 1 (or 0) for air.
+    pub ior: f64,
+    /// Diameter of the surface, anything hitting outside it is blocked.
+    pub aperture: f64,
+}
+
+impl LensElement {
+    pub fn new(radius: f64, thickness: f64, ior: f64, aperture: f64) -> LensElement {
+        Self {
+            radius,
+            thickness,
+            ior,
+            aperture,
+        }
+    }
+
+    fn scaled(self, scale: f64) -> LensElement {
+        Self {
+            radius: self.radius * scale,
+            thickness: self.thickness * scale,
+            aperture: self.aperture * scale,
+            ior: self.ior,
+        }
+    }
+
+    fn medium(&self) -> f64 {
+        if self.ior == 0.0 {
+            1.0
+        } else {
+            self.ior
+        }
+    }
+
+    /// Takes `ray` through this surface with its vertex at `z`, refracting
+    /// with relative index `eta` (incident over transmitted). The stop lets
+    /// light through where `aperture` does.
+    fn interact(&self, ray: Ray<f64>, z: f64, eta: f64, aperture: &Aperture) -> Option<Ray<f64>> {
+        let (origin, direction) = (*ray.get_origin(), ray.get_direction().unit_vector());
+        let radius = self.aperture / 2.0;
+
+        if self.radius == 0.0 {
+            if direction.z == 0.0 {
+                return None;
+            }
+            let hit = ray.at((z - origin.z) / ray.get_direction().z);
+            return aperture
+                .contains(Vec3::new(hit.x / radius, hit.y / radius, 0.0))
+                .then(|| Ray::new(hit, direction));
+        }
+
+        let center = Vec3::new(0.0, 0.0, z + self.radius);
+        let oc = origin - center;
+        let b = oc.dot(&direction);
+        let c = oc.length_squared() - self.radius * self.radius;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        // the surface's vertex is on the nearer side of the sphere for
+        // exactly one of the two directions along the axis
+        let t = if (direction.z > 0.0) ^ (self.radius < 0.0) {
+            -b - root
+        } else {
+            -b + root
+        };
+        if t <= 0.0 {
+            return None;
+        }
+        let hit = origin + direction * t;
+        if hit.x * hit.x + hit.y * hit.y > radius * radius {
+            return None;
+        }
+
+        let mut normal = (hit - center).unit_vector();
+        if normal.dot(&direction) > 0.0 {
+            normal *= -1.0;
+        }
+        let cos_theta = -direction.dot(&normal);
+        if eta * eta * (1.0 - cos_theta * cos_theta) >= 1.0 {
+            // total internal reflection, lost inside the barrel
+            return None;
+        }
+        Some(Ray::new(hit, direction.refract(normal, eta)))
+    }
+}
+
+/// A real lens, traced element by element, which brings its own distortion
+/// and vignetting. The prescription is in millimetres and the scene is taken
+/// to be in metres.
+///
+/// Everything here works in lens space: the film at z = 0, the elements in
+/// front of it towards -z and the optical axis along z.
+#[derive(Debug, Clone)]
+pub struct LensSystem {
+    elements: Vec<LensElement>,
+    film_width: f64,
+    film_height: f64,
+}
+
+impl LensSystem {
+    pub fn new(elements: Vec<LensElement>) -> LensSystem {
+        let elements = elements.into_iter().map(|e| e.scaled(0.001)).collect();
+        Self {
+            elements,
+            film_width: 0.0,
+            film_height: 0.0,
+        }
+    }
+
+    /// A 50mm double Gauss design, the classic normal lens.
+    pub fn double_gauss() -> LensSystem {
+        Self::new(vec![
+            LensElement::new(29.475, 3.76, 1.67, 25.2),
+            LensElement::new(84.83, 0.12, 1.0, 25.2),
+            LensElement::new(19.275, 4.025, 1.67, 23.0),
+            LensElement::new(40.77, 3.275, 1.699, 23.0),
+            LensElement::new(12.75, 5.705, 1.0, 18.0),
+            LensElement::new(0.0, 4.5, 0.0, 17.1),
+            LensElement::new(-14.495, 1.18, 1.603, 17.0),
+            LensElement::new(40.77, 6.065, 1.658, 20.0),
+            LensElement::new(-20.385, 0.19, 1.0, 20.0),
+            LensElement::new(437.065, 3.22, 1.717, 20.0),
+            LensElement::new(-39.73, 5.0, 1.0, 20.0),
+        ])
+    }
+
+    /// Focal length, found by tracing a ray parallel to the axis in from the
+    /// scene. `None` if it doesn't make it through.
+    pub fn focal_length(&self) -> Option<f64> {
+        let height = self.elements.first()?.aperture / 2.0 * 0.01;
+        let ray_in = Ray::new(
+            Vec3::new(height, 0.0, self.front_z() - 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        let ray_out = self.trace_from_scene(ray_in)?;
+        let (origin, direction) = (ray_out.get_origin(), ray_out.get_direction());
+        // where the ray crosses the axis, and where it meets the incoming ray
+        let focal_z = origin.z - origin.x * direction.z / direction.x;
+        let principal_z = origin.z + (height - origin.x) * direction.z / direction.x;
+        Some(focal_z - principal_z)
+    }
+
+    /// Moves the film so things `focus_dist` in front of it are sharp, and
+    /// sizes it to give a vertical field of view of `vfov` degrees.
+    pub fn fit(&mut self, vfov: f64, aspect_ratio: f64, focus_dist: f64) {
+        for _ in 0..8 {
+            let Some(front) = self.elements.first() else {
+                return;
+            };
+            let object = Vec3::new(0.0, 0.0, -focus_dist);
+            let towards = Vec3::new(front.aperture / 2.0 * 0.01, 0.0, self.front_z());
+            let Some(ray_out) = self.trace_from_scene(Ray::new(object, towards - object)) else {
+                break;
+            };
+            let (origin, direction) = (ray_out.get_origin(), ray_out.get_direction());
+            if direction.x == 0.0 {
+                break;
+            }
+            // move the film to where the ray crosses the axis
+            let image_z = origin.z - origin.x * direction.z / direction.x;
+            let back = self.elements.last_mut().unwrap();
+            back.thickness = (back.thickness + image_z).max(0.0);
+            if image_z.abs() < 1e-9 {
+                break;
+            }
+        }
+
+        let focal_length = self.focal_length().unwrap_or(0.05);
+        self.film_height = 2.0 * focal_length * (vfov.to_radians() / 2.0).tan();
+        self.film_width = self.film_height * aspect_ratio;
+    }
+
+    /// Ray leaving the front of the lens for image position `(s, t)`, both
+    /// in [0, 1] from the top left, and a point `u` on the back element.
+    /// `None` if the lens blocks it.
+    ///
+    /// Along with it comes the weight of the sample, cos⁴ of the angle it
+    /// leaves the film at. That's the irradiance on the film, relative to a
+    /// ray straight down the axis, so the middle of the image is exposed
+    /// like the thin lens and the edges fall off naturally.
+    pub fn ray(
+        &self,
+        s: f64,
+        t: f64,
+        u: (f64, f64),
+        aperture: &Aperture,
+    ) -> Option<(Ray<f64>, f64)> {
+        let back = self.elements.last()?;
+        // the image on the film is upside down
+        let film = Vec3::new(
+            (0.5 - s) * self.film_width,
+            (t - 0.5) * self.film_height,
+            0.0,
+        );
+        let p = sample_unit_disk(u) * (back.aperture / 2.0);
+        let on_back = Vec3::new(p.x, p.y, -back.thickness);
+        let cos_theta = (on_back - film).unit_vector().z.abs();
+        let ray = self.trace_from_film(Ray::new(film, on_back - film), aperture)?;
+        Some((ray, cos_theta.powi(4)))
+    }
+
+    fn front_z(&self) -> f64 {
+        -self.elements.iter().map(|e| e.thickness).sum::<f64>()
+    }
+
+    fn trace_from_film(&self, ray: Ray<f64>, aperture: &Aperture) -> Option<Ray<f64>> {
+        let mut ray = ray;
+        let mut element_z = 0.0;
+        for (i, element) in self.elements.iter().enumerate().rev() {
+            element_z -= element.thickness;
+            let behind = element.medium();
+            let in_front = if i > 0 {
+                self.elements[i - 1].medium()
+            } else {
+                1.0
+            };
+            ray = element.interact(ray, element_z, behind / in_front, aperture)?;
+        }
+        Some(ray)
+    }
+
+    fn trace_from_scene(&self, ray: Ray<f64>) -> Option<Ray<f64>> {
+        let mut ray = ray;
+        let mut element_z = self.front_z();
+        for (i, element) in self.elements.iter().enumerate() {
+            let in_front = if i > 0 {
+                self.elements[i - 1].medium()
+            } else {
+                1.0
+            };
+            let behind = element.medium();
+            ray = element.interact(ray, element_z, in_front / behind, &Aperture::Circle)?;
+            element_z += element.thickness;
+        }
+        Some(ray)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cats_eye_resamples_instead_of_blocking() {
+        let lens = Lens::new(Aperture::Circle).with_cats_eye(1.0);
+        for i in 0..64 {
+            let u = ((i % 8) as f64 / 8.0 + 0.01, (i / 8) as f64 / 8.0 + 0.01);
+            let p = lens.sample(u, (0.8, 0.6)).unwrap();
+            assert!(p.x * p.x + p.y * p.y <= 1.0);
+            assert!((p.x - 0.8).powi(2) + (p.y - 0.6).powi(2) <= 1.0);
+        }
+    }
+}
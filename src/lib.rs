@@ -1,9 +1,29 @@
+pub mod adaptive;
+pub mod animation;
+pub mod aov;
 pub mod camera;
+pub mod checkpoint;
 mod color;
+pub mod dither;
+pub mod film;
+pub mod filter;
 pub mod hit;
+pub mod image;
+pub mod integrator;
 mod interval;
+pub mod lens;
 pub mod material;
+pub mod microfacet;
+pub mod physical;
+pub mod progressive;
+pub mod projection;
 mod ray;
+pub mod sampler;
+pub mod spectrum;
 pub mod sphere;
+pub mod stereo;
+pub mod thin_film;
+pub mod tile;
+pub mod tonemap;
 pub mod utils;
 mod vec3;
@@ -1,12 +1,27 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use raytrace::{
+    animation::CameraPath,
     camera::Camera,
     hit::HittableList,
+    integrator::SpectralPathTracer,
     material::{Dielectric, Lambertian, Material, Metal},
+    spectrum::Ior,
     sphere::Sphere,
-    utils::{rand_float, rand_from_range, Color, Point, Rc},
+    utils::{Color, Point, Rc},
 };
+use std::{env, io, ops::RangeInclusive};
 
-fn main() {
+/// What the scene's random spheres come from when rendering frames, so
+/// every frame shows the same scene.
+const SCENE_SEED: u64 = 42;
+
+/// Parses `N..M` (both included) from the command line.
+fn frame_range(arg: &str) -> Option<RangeInclusive<u32>> {
+    let (first, last) = arg.split_once("..")?;
+    Some(first.parse().ok()?..=last.parse().ok()?)
+}
+
+fn scene(rng: &mut impl Rng) -> HittableList<f64> {
     let mut world: HittableList<f64> = HittableList::new();
 
     let ground = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
@@ -18,20 +33,22 @@ fn main() {
 
     for a in -11..11 {
         for b in -11..11 {
-            let choose_mat = rand_float();
+            let choose_mat = rng.gen::<f64>();
             let center = Point::new(
-                a as f64 + 0.9 * rand_float(),
+                a as f64 + 0.9 * rng.gen::<f64>(),
                 0.2,
-                b as f64 + 0.9 * rand_float(),
+                b as f64 + 0.9 * rng.gen::<f64>(),
             );
             if (center - Point::new(4.0, 0.2, 0.0)).length() > 0.9 {
                 let sphere_material: Rc<dyn Material<f64>> = {
                     if choose_mat < 0.8 {
-                        let albedo = Color::<f64>::rand().mul_vec3(Color::<f64>::rand());
+                        let mut color = || Color::new(rng.gen(), rng.gen(), rng.gen());
+                        let albedo = color().mul_vec3(color());
                         Rc::new(Lambertian::new(albedo))
                     } else if choose_mat < 0.95 {
-                        let albedo = Color::<f64>::rand_from_range(0.5, 1.0);
-                        let fuzziness = rand_from_range(0.0..0.5);
+                        let mut channel = || rng.gen_range(0.5..1.0);
+                        let albedo = Color::new(channel(), channel(), channel());
+                        let fuzziness = rng.gen_range(0.0..0.5);
                         Rc::new(Metal::new(albedo, Some(fuzziness)))
                     } else {
                         Rc::new(Dielectric::new(1.5))
@@ -42,7 +59,7 @@ fn main() {
         }
     }
 
-    let material1 = Rc::new(Dielectric::new(1.5));
+    let material1 = Rc::new(Dielectric::dispersive(Ior::bk7()));
     world.add(Rc::new(Sphere::new(
         Point::new(0.0, 1.0, 0.0),
         1.0,
@@ -63,7 +80,53 @@ fn main() {
         material3,
     )));
 
-    let camera = Camera::default();
+    world
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().collect();
 
-    camera.render(world);
+    // `--spectral` renders with wavelengths instead of RGB, so the glass
+    // sphere shows some dispersion
+    let mut camera = Camera::default();
+    if args.iter().any(|arg| arg == "--spectral") {
+        camera = camera.with_integrator(Box::new(SpectralPathTracer::new(50, HittableList::new())));
+    }
+
+    // `--frames N..M [--output pattern] [--path keyframes.txt]` renders
+    // those frames to numbered files instead of one image to stdout, along
+    // the keyframes in the file or else a turntable
+    let flag = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|i| args.get(i + 1))
+    };
+    match flag("--frames") {
+        Some(frames) => {
+            let frames = frame_range(frames).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "--frames wants N..M")
+            })?;
+            let output = flag("--output").map_or("frame_####.ppm", |o| o.as_str());
+            let path = match flag("--path") {
+                Some(path) => CameraPath::load(path)?,
+                None => {
+                    // the last frame stops one short of the first so it loops
+                    let span = (*frames.start() as f64, *frames.end() as f64 + 1.0);
+                    CameraPath::orbit(
+                        Point::new(13.0, 2.0, 3.0),
+                        Point::new(0.0, 0.0, 0.0),
+                        20.0,
+                        span,
+                    )
+                }
+            };
+            let world = scene(&mut StdRng::seed_from_u64(SCENE_SEED));
+            camera.render_frames(world, &path, frames, output)
+        }
+        None if flag("--path").is_some() => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--path needs --frames",
+        )),
+        None => camera.render(scene(&mut rand::thread_rng())),
+    }
 }
@@ -1,11 +1,64 @@
 use crate::{
     hit::HitRecord,
-    utils::{rand_float, rand_in_unit_sphere, Color, Float, Ray, Vec3},
+    microfacet::{fresnel_conductor, fresnel_dielectric, fresnel_schlick, refract, Ggx, Gtr1},
+    sampler::{sample_in_unit_sphere, sample_unit_vector, Sampler},
+    spectrum::{rgb_to_spectrum, Ior, Wavelengths},
+    thin_film::{ReflectanceTable, ThinFilm},
+    utils::{luminance, Color, Float, Onb, Ray, Rc, Vec3, PI},
 };
 use std::fmt;
 
 pub trait Material<T> {
-    fn scatter(&self, ray_in: &Ray<T>, record: &HitRecord<T>) -> Option<(Color<T>, Ray<T>)>;
+    fn scatter(
+        &self,
+        ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color<T>, Ray<T>)>;
+
+    /// Light given off by the surface, if any.
+    fn emitted(&self, _ray_in: &Ray<T>, _record: &HitRecord<T>) -> Option<Color<T>> {
+        None
+    }
+
+    /// Base color of the surface, for albedo passes.
+    fn albedo(&self, _record: &HitRecord<T>) -> Option<Color<T>> {
+        None
+    }
+
+    /// Density `scatter` would pick `scattered` with, or `None` for a delta
+    /// (perfectly specular) lobe that can't be evaluated for other directions.
+    fn scattering_pdf(
+        &self,
+        _ray_in: &Ray<T>,
+        _record: &HitRecord<T>,
+        _scattered: &Ray<T>,
+    ) -> Option<T> {
+        None
+    }
+
+    /// The BSDF times the cosine towards `scattered`, for sampling lights.
+    /// The default works for materials whose `scatter` follows the BSDF
+    /// exactly, so the attenuation is the same whichever way it goes.
+    fn eval(&self, ray_in: &Ray<T>, record: &HitRecord<T>, scattered: &Ray<T>) -> Option<Color<T>>
+    where
+        T: Float,
+    {
+        let pdf = self.scattering_pdf(ray_in, record, scattered)?;
+        Some(self.albedo(record)? * pdf)
+    }
+
+    /// Beer-Lambert absorption coefficient per unit distance inside the
+    /// object, for materials light goes through.
+    fn absorption(&self) -> Option<Color<T>> {
+        None
+    }
+
+    /// Whether, rendering spectrally, the response only holds at the hero
+    /// wavelength, so the others have to go before sampling a light too.
+    fn wavelength_selective(&self) -> bool {
+        false
+    }
 }
 
 impl<T> fmt::Debug for dyn Material<T> {
@@ -30,9 +83,14 @@ where
     T: Float + From<f64>,
     Vec3<T>: From<Vec3<f64>>,
 {
-    fn scatter(&self, _ray_in: &Ray<T>, record: &HitRecord<T>) -> Option<(Color<T>, Ray<T>)> {
+    fn scatter(
+        &self,
+        _ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color<T>, Ray<T>)> {
         let mut scatter_direction =
-            record.normal + rand_in_unit_sphere().map(Into::into).unit_vector();
+            record.normal + sample_unit_vector(sampler.get_2d()).map(Into::into);
 
         if scatter_direction.near_zero() {
             scatter_direction = record.normal;
@@ -42,6 +100,20 @@ where
         let attenuation = self.albedo;
         Some((attenuation, scattered))
     }
+
+    fn scattering_pdf(
+        &self,
+        _ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        scattered: &Ray<T>,
+    ) -> Option<T> {
+        let cos_theta = record.normal.dot(&scattered.get_direction().unit_vector());
+        Some(cos_theta.max(T::zero()) / <T as From<f64>>::from(PI))
+    }
+
+    fn albedo(&self, _record: &HitRecord<T>) -> Option<Color<T>> {
+        Some(self.albedo)
+    }
 }
 
 #[derive(Debug)]
@@ -66,24 +138,803 @@ impl<T> Material<T> for Metal<T>
 where
     T: Float + From<f64>,
 {
-    fn scatter(&self, ray_in: &Ray<T>, record: &HitRecord<T>) -> Option<(Color<T>, Ray<T>)> {
+    fn scatter(
+        &self,
+        ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color<T>, Ray<T>)> {
+        let fuzz = sample_in_unit_sphere(sampler.get_2d(), sampler.get_1d());
         let mut reflected = ray_in.get_direction().reflect(record.normal);
-        reflected =
-            reflected.unit_vector() + rand_in_unit_sphere().map(Into::into) * self.fuzziness;
+        reflected = reflected.unit_vector() + fuzz.map(Into::into) * self.fuzziness;
         let scattered = Ray::new(record.point, reflected);
         let attenuation = self.albedo;
         Some((attenuation, scattered))
     }
+
+    fn albedo(&self, _record: &HitRecord<T>) -> Option<Color<T>> {
+        Some(self.albedo)
+    }
+}
+
+/// Shading frame around the normal at the hit, and the direction back
+/// along the incoming ray in it. The microfacet models work in f64.
+fn shading_frame<T: Float>(ray_in: &Ray<T>, record: &HitRecord<T>) -> (Onb<f64>, Vec3<f64>) {
+    let to_f64 = |v: &Vec3<T>| v.map(|c| c.to_f64().unwrap_or(0.0));
+    let frame = Onb::new(&to_f64(&record.normal));
+    let wo = frame.to_local(to_f64(ray_in.get_direction()).unit_vector() * -1.0);
+    (frame, wo)
+}
+
+/// The unit direction `scattered` leaves in, in `frame`'s local space.
+fn local_direction<T: Float>(frame: &Onb<f64>, scattered: &Ray<T>) -> Vec3<f64> {
+    let direction = scattered.get_direction().map(|c| c.to_f64().unwrap_or(0.0));
+    frame.to_local(direction).unit_vector()
+}
+
+fn reflect_local(wo: Vec3<f64>, h: Vec3<f64>) -> Vec3<f64> {
+    h * (2.0 * wo.dot(&h)) - wo
+}
+
+/// A metal with a rough surface of GGX distributed microfacets and exact
+/// Fresnel for its complex index of refraction `eta + i k`, given per RGB
+/// channel. Unlike `Metal` it doesn't gain energy as it gets rougher.
+#[derive(Debug, Copy, Clone)]
+pub struct Conductor {
+    eta: Color<f64>,
+    k: Color<f64>,
+    distribution: Ggx,
+    film: Option<(ThinFilm, ReflectanceTable)>,
+}
+
+impl Conductor {
+    pub fn new(eta: Color<f64>, k: Color<f64>, roughness: f64) -> Conductor {
+        Self {
+            eta,
+            k,
+            distribution: Ggx::from_roughness(roughness),
+            film: None,
+        }
+    }
+
+    /// An oxide or other transparent layer on top, like heat tinted steel
+    /// or anodized titanium.
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some((film, film.tabulate(1.0, self.eta, self.k)));
+        self
+    }
+
+    /// Fresnel reflectance, at the hero wavelength alone when rendering
+    /// spectrally with a film.
+    fn fresnel(&self, cos_theta: f64, wavelengths: Option<Wavelengths>) -> Color<f64> {
+        match (&self.film, wavelengths) {
+            (None, _) => fresnel_conductor(cos_theta, self.eta, self.k),
+            (Some((film, _)), Some(wavelengths)) => {
+                let wavelength = wavelengths.hero();
+                let eta = rgb_to_spectrum(self.eta, wavelength);
+                let k = rgb_to_spectrum(self.k, wavelength);
+                Color::splat(film.reflectance(cos_theta, 1.0, eta, k, wavelength))
+            }
+            (Some((_, table)), None) => table.at(cos_theta),
+        }
+    }
+
+    pub fn gold(roughness: f64) -> Conductor {
+        Self::new(
+            Color::new(0.143, 0.374, 1.442),
+            Color::new(3.983, 2.385, 1.603),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> Conductor {
+        Self::new(
+            Color::new(0.200, 0.924, 1.102),
+            Color::new(3.912, 2.452, 2.142),
+            roughness,
+        )
+    }
+
+    pub fn aluminum(roughness: f64) -> Conductor {
+        Self::new(
+            Color::new(1.657, 0.880, 0.521),
+            Color::new(9.224, 6.270, 4.837),
+            roughness,
+        )
+    }
+
+    pub fn silver(roughness: f64) -> Conductor {
+        Self::new(
+            Color::new(0.155, 0.117, 0.138),
+            Color::new(4.827, 3.122, 2.147),
+            roughness,
+        )
+    }
+}
+
+impl<T> Material<T> for Conductor
+where
+    T: Float + From<f64>,
+    Vec3<T>: From<Vec3<f64>>,
+{
+    fn scatter(
+        &self,
+        ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color<T>, Ray<T>)> {
+        let (frame, wo) = shading_frame(ray_in, record);
+        let u = sampler.get_2d();
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let h = if self.distribution.is_smooth() {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            self.distribution.sample_visible(wo, u)
+        };
+        // whatever would go below the surface is lost, the single
+        // scattering model doesn't follow it between the microfacets
+        let wi = reflect_local(wo, h);
+        if wi.z <= 0.0 {
+            return None;
+        }
+
+        let fresnel = self.fresnel(wo.dot(&h), ray_in.wavelengths());
+        let attenuation = if self.distribution.is_smooth() {
+            fresnel
+        } else {
+            fresnel * (self.distribution.g(wo, wi) / self.distribution.g1(wo))
+        };
+        let wavelengths = match self.film {
+            Some(_) => ray_in.wavelengths().map(|w| w.terminate_secondary()),
+            None => ray_in.wavelengths(),
+        };
+        Some((
+            attenuation.into(),
+            Ray::new(record.point, frame.transform(wi).into()).with_wavelengths(wavelengths),
+        ))
+    }
+
+    fn scattering_pdf(
+        &self,
+        ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        scattered: &Ray<T>,
+    ) -> Option<T> {
+        if self.distribution.is_smooth() {
+            return None;
+        }
+        let (frame, wo) = shading_frame(ray_in, record);
+        let wi = local_direction(&frame, scattered);
+        if wi.z <= 0.0 || wo.z <= 0.0 {
+            return Some(T::zero());
+        }
+        let h = (wo + wi).unit_vector();
+        let pdf = self.distribution.pdf_visible(wo, h) / (4.0 * wo.dot(&h));
+        Some(<T as From<f64>>::from(pdf))
+    }
+
+    fn eval(&self, ray_in: &Ray<T>, record: &HitRecord<T>, scattered: &Ray<T>) -> Option<Color<T>> {
+        if self.distribution.is_smooth() {
+            return None;
+        }
+        let (frame, wo) = shading_frame(ray_in, record);
+        let wi = local_direction(&frame, scattered);
+        if wi.z <= 0.0 || wo.z <= 0.0 {
+            return Some(Color::splat(T::zero()));
+        }
+        let h = (wo + wi).unit_vector();
+        let fresnel = self.fresnel(wo.dot(&h), ray_in.wavelengths());
+        // f cos(wi) = F D G / (4 cos(wo))
+        let value = fresnel * (self.distribution.d(h) * self.distribution.g(wo, wi) / (4.0 * wo.z));
+        Some(value.into())
+    }
+
+    fn albedo(&self, _record: &HitRecord<T>) -> Option<Color<T>> {
+        Some(self.fresnel(1.0, None).into())
+    }
+    /// Thin film interference, see `fresnel`.
+    fn wavelength_selective(&self) -> bool {
+        self.film.is_some()
+    }
+}
+
+/// Glass with a rough surface: reflection and refraction through GGX
+/// microfacets after Walter et al., "Microfacet Models for Refraction
+/// through Rough Surfaces" (2007), with exact Fresnel.
+#[derive(Debug, Copy, Clone)]
+pub struct RoughDielectric {
+    ior: f64,
+    distribution: Ggx,
+}
+
+impl RoughDielectric {
+    pub fn new(ior: f64, roughness: f64) -> RoughDielectric {
+        Self {
+            ior,
+            distribution: Ggx::from_roughness(roughness),
+        }
+    }
+
+    /// Lightly acid etched, still see-through up close.
+    pub fn etched_glass() -> RoughDielectric {
+        Self::new(1.5, 0.2)
+    }
+
+    pub fn frosted_glass() -> RoughDielectric {
+        Self::new(1.5, 0.4)
+    }
+
+    /// Barely lets shapes through at all.
+    pub fn sandblasted_glass() -> RoughDielectric {
+        Self::new(1.5, 0.7)
+    }
+
+    /// Index on the far side of the surface over the near side's.
+    fn eta<T: Float>(&self, record: &HitRecord<T>) -> f64 {
+        if record.front_face() {
+            self.ior
+        } else {
+            1.0 / self.ior
+        }
+    }
+
+    /// The microfacet normal that takes `wo` to `wi`, facing out of the
+    /// surface, for reflection when they're on the same side.
+    fn half_vector(wo: Vec3<f64>, wi: Vec3<f64>, eta: f64) -> Vec3<f64> {
+        let h = if wi.z > 0.0 { wo + wi } else { wo + wi * eta };
+        let h = h.unit_vector();
+        if h.z < 0.0 {
+            h * -1.0
+        } else {
+            h
+        }
+    }
+
+    /// `eval` and `scattering_pdf` together, they share most of the work.
+    fn evaluate(&self, wo: Vec3<f64>, wi: Vec3<f64>, eta: f64) -> (f64, f64) {
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return (0.0, 0.0);
+        }
+        let h = Self::half_vector(wo, wi, eta);
+        let (wo_h, wi_h) = (wo.dot(&h), wi.dot(&h));
+        let fresnel = fresnel_dielectric(wo_h, eta);
+        let d = self.distribution.d(h);
+        let g = self.distribution.g(wo, wi);
+        let pdf_h = self.distribution.pdf_visible(wo, h);
+
+        if wi.z > 0.0 {
+            let value = fresnel * d * g / (4.0 * wo.z);
+            return (value, fresnel * pdf_h / (4.0 * wo_h));
+        }
+
+        // both have to see the same side of the microfacet
+        if wo_h <= 0.0 || wi_h >= 0.0 {
+            return (0.0, 0.0);
+        }
+        let denominator = (wo_h + eta * wi_h).powi(2);
+        let jacobian = eta * eta * wi_h.abs() / denominator;
+        let value = (1.0 - fresnel) * d * g * wo_h * jacobian / wo.z;
+        (value, (1.0 - fresnel) * pdf_h * jacobian)
+    }
+}
+
+impl<T> Material<T> for RoughDielectric
+where
+    T: Float + From<f64>,
+    Vec3<T>: From<Vec3<f64>>,
+{
+    fn scatter(
+        &self,
+        ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color<T>, Ray<T>)> {
+        let (frame, wo) = shading_frame(ray_in, record);
+        let (u, choice) = (sampler.get_2d(), sampler.get_1d());
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let eta = self.eta(record);
+        let h = if self.distribution.is_smooth() {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            self.distribution.sample_visible(wo, u)
+        };
+
+        // pick reflection or refraction by the Fresnel term, which then
+        // cancels out of the weight
+        let fresnel = fresnel_dielectric(wo.dot(&h), eta);
+        let wi = match refract(wo, h, eta) {
+            Some(wi) if choice >= fresnel => wi,
+            _ => reflect_local(wo, h),
+        };
+        // a microfacet can send light out the wrong side of the surface,
+        // which the model has nothing to say about
+        if (wi.z > 0.0) != (wo.dot(&h) * wi.dot(&h) > 0.0) || wi.z == 0.0 {
+            return None;
+        }
+
+        let weight = if self.distribution.is_smooth() {
+            1.0
+        } else {
+            self.distribution.g(wo, wi) / self.distribution.g1(wo)
+        };
+        Some((
+            Color::splat(<T as From<f64>>::from(weight)),
+            Ray::new(record.point, frame.transform(wi).into()),
+        ))
+    }
+
+    fn scattering_pdf(
+        &self,
+        ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        scattered: &Ray<T>,
+    ) -> Option<T> {
+        if self.distribution.is_smooth() {
+            return None;
+        }
+        let (frame, wo) = shading_frame(ray_in, record);
+        let wi = local_direction(&frame, scattered);
+        let (_, pdf) = self.evaluate(wo, wi, self.eta(record));
+        Some(<T as From<f64>>::from(pdf))
+    }
+
+    fn eval(&self, ray_in: &Ray<T>, record: &HitRecord<T>, scattered: &Ray<T>) -> Option<Color<T>> {
+        if self.distribution.is_smooth() {
+            return None;
+        }
+        let (frame, wo) = shading_frame(ray_in, record);
+        let wi = local_direction(&frame, scattered);
+        let (value, _) = self.evaluate(wo, wi, self.eta(record));
+        Some(Color::splat(<T as From<f64>>::from(value)))
+    }
+
+    fn albedo(&self, _record: &HitRecord<T>) -> Option<Color<T>> {
+        Some(Color::splat(T::one()))
+    }
+}
+
+/// Disney's principled BSDF, after Burley, "Physically Based Shading at
+/// Disney" (2012) and the 2015 follow up for transmission. A handful of
+/// parameters in [0, 1] blend a diffuse base with sheen and subsurface
+/// flattening, metal, rough glass and a clear lacquer on top.
+#[derive(Debug, Copy, Clone)]
+pub struct Principled {
+    base_color: Color<f64>,
+    metallic: f64,
+    roughness: f64,
+    distribution: Ggx,
+    specular: f64,
+    sheen: f64,
+    sheen_tint: f64,
+    clearcoat: f64,
+    clearcoat_gloss: f64,
+    transmission: f64,
+    ior: f64,
+    subsurface: f64,
+}
+
+impl Principled {
+    /// A dielectric of `base_color` with a moderately rough 4% specular.
+    pub fn new(base_color: Color<f64>) -> Principled {
+        Self {
+            base_color,
+            metallic: 0.0,
+            roughness: 0.5,
+            distribution: Ggx::from_roughness(0.5),
+            specular: 0.5,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            ior: 1.5,
+            subsurface: 0.0,
+        }
+    }
+
+    /// Close to `Lambertian`, though it still brightens a little at
+    /// grazing angles.
+    pub fn diffuse(base_color: Color<f64>) -> Principled {
+        Self::new(base_color).with_specular(0.0).with_roughness(0.5)
+    }
+
+    pub fn metal(base_color: Color<f64>, roughness: f64) -> Principled {
+        Self::new(base_color)
+            .with_metallic(1.0)
+            .with_roughness(roughness)
+    }
+
+    pub fn glass(ior: f64, roughness: f64) -> Principled {
+        Self::new(Color::splat(1.0))
+            .with_transmission(1.0, ior)
+            .with_roughness(roughness)
+    }
+
+    pub fn with_metallic(mut self, metallic: f64) -> Self {
+        self.metallic = metallic.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: f64) -> Self {
+        self.roughness = roughness.clamp(0.0, 1.0);
+        self.distribution = Ggx::from_roughness(roughness);
+        self
+    }
+
+    /// Strength of the dielectric specular, 0.5 being the 4% of most
+    /// materials and 1 the 8% of gemstones.
+    pub fn with_specular(mut self, specular: f64) -> Self {
+        self.specular = specular.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Extra reflection at grazing angles for cloth, `tint` moving its color
+    /// from white towards the base color.
+    pub fn with_sheen(mut self, sheen: f64, tint: f64) -> Self {
+        self.sheen = sheen.clamp(0.0, 1.0);
+        self.sheen_tint = tint.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_clearcoat(mut self, clearcoat: f64, gloss: f64) -> Self {
+        self.clearcoat = clearcoat.clamp(0.0, 1.0);
+        self.clearcoat_gloss = gloss.clamp(0.0, 1.0);
+        self
+    }
+
+    /// How much of the dielectric base is glass rather than diffuse. Light
+    /// refracting in or out is tinted by the square root of the base color,
+    /// so it's the base color after going through a closed object.
+    pub fn with_transmission(mut self, transmission: f64, ior: f64) -> Self {
+        self.transmission = transmission.clamp(0.0, 1.0);
+        self.ior = ior;
+        self
+    }
+
+    /// Flattens the diffuse the way light scattering under the surface does,
+    /// without actually going under it.
+    pub fn with_subsurface(mut self, subsurface: f64) -> Self {
+        self.subsurface = subsurface.clamp(0.0, 1.0);
+        self
+    }
+
+    fn specular_f0(&self) -> Color<f64> {
+        let dielectric = Color::splat(0.08 * self.specular);
+        dielectric * (1.0 - self.metallic) + self.base_color * self.metallic
+    }
+
+    fn glass_lobe(&self) -> RoughDielectric {
+        RoughDielectric {
+            ior: self.ior,
+            distribution: self.distribution,
+        }
+    }
+
+    fn clearcoat_lobe(&self) -> Gtr1 {
+        Gtr1 {
+            alpha: 0.1 + (0.001 - 0.1) * self.clearcoat_gloss,
+        }
+    }
+
+    /// Hit from inside something that lets light through, where only the
+    /// glass makes sense.
+    fn inside<T: Float>(&self, record: &HitRecord<T>) -> bool {
+        !record.front_face() && self.transmission > 0.0
+    }
+
+    /// How much of the BSDF is diffuse, specular, glass and clearcoat.
+    fn weights(&self, inside: bool) -> [f64; 4] {
+        if inside {
+            return [0.0, 0.0, 1.0, 0.0];
+        }
+        let dielectric = 1.0 - self.metallic;
+        [
+            dielectric * (1.0 - self.transmission),
+            1.0 - dielectric * self.transmission,
+            dielectric * self.transmission,
+            0.25 * self.clearcoat,
+        ]
+    }
+
+    /// Chances of sampling each lobe, simply in proportion to its weight.
+    fn probabilities(&self, inside: bool) -> [f64; 4] {
+        let weights = self.weights(inside);
+        let total: f64 = weights.iter().sum();
+        weights.map(|w| w / total)
+    }
+
+    /// Whether `wi` is the mirror or refracted direction of a smooth
+    /// surface, which only the delta lobes could have picked.
+    fn is_specular(&self, wo: Vec3<f64>, wi: Vec3<f64>, inside: bool, eta: f64) -> bool {
+        let probabilities = self.probabilities(inside);
+        if !self.distribution.is_smooth() || probabilities[1] + probabilities[2] == 0.0 {
+            return false;
+        }
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let close = |v: Vec3<f64>| (v - wi).length() < 1e-6;
+        close(reflect_local(wo, normal)) || refract(wo, normal, eta).is_some_and(close)
+    }
+
+    /// The BSDF times the cosine and the density of sampling `wi`, summed
+    /// over the lobes that aren't delta functions.
+    fn evaluate(&self, wo: Vec3<f64>, wi: Vec3<f64>, inside: bool, eta: f64) -> (Color<f64>, f64) {
+        let mut value = Color::splat(0.0);
+        let mut pdf = 0.0;
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return (value, pdf);
+        }
+        let weights = self.weights(inside);
+        let probabilities = self.probabilities(inside);
+        let smooth = self.distribution.is_smooth();
+
+        if wi.z > 0.0 && !inside {
+            let h = (wo + wi).unit_vector();
+            let cos_d = wi.dot(&h);
+            let (fl, fv) = ((1.0 - wi.z).powi(5), (1.0 - wo.z).powi(5));
+
+            // retro-reflection at grazing angles for rough surfaces, and
+            // Hanrahan-Krueger like flattening for subsurface
+            let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+            let diffuse = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv);
+            let fss90 = self.roughness * cos_d * cos_d;
+            let fss = (1.0 + (fss90 - 1.0) * fl) * (1.0 + (fss90 - 1.0) * fv);
+            let ss = 1.25 * (fss * (1.0 / (wi.z + wo.z) - 0.5) + 0.5);
+            let lambert = (diffuse + (ss - diffuse) * self.subsurface) / PI;
+
+            let lum = luminance(self.base_color);
+            let tint = if lum > 0.0 {
+                self.base_color / lum
+            } else {
+                Color::splat(1.0)
+            };
+            let sheen_color = Color::splat(1.0 - self.sheen_tint) + tint * self.sheen_tint;
+            let sheen = sheen_color * (self.sheen * (1.0 - cos_d).powi(5));
+
+            value += (self.base_color * lambert + sheen) * (weights[0] * wi.z);
+            pdf += probabilities[0] * wi.z / PI;
+
+            if !smooth {
+                let fresnel = fresnel_schlick(wo.dot(&h), self.specular_f0());
+                let d = self.distribution.d(h);
+                let g = self.distribution.g(wo, wi);
+                value += fresnel * (weights[1] * d * g / (4.0 * wo.z));
+                pdf += probabilities[1] * self.distribution.pdf_visible(wo, h) / (4.0 * wo.dot(&h));
+            }
+
+            if self.clearcoat > 0.0 {
+                let coat = self.clearcoat_lobe();
+                let fresnel = fresnel_schlick(wo.dot(&h), Color::splat(0.04)).x;
+                let f = fresnel * coat.d(h) * coat.g(wo, wi) / (4.0 * wo.z);
+                value += Color::splat(weights[3] * f);
+                pdf += probabilities[3] * coat.pdf(h) / (4.0 * wo.dot(&h));
+            }
+        }
+
+        if probabilities[2] > 0.0 && !smooth {
+            let (f, lobe_pdf) = self.glass_lobe().evaluate(wo, wi, eta);
+            let tint = if wi.z < 0.0 {
+                self.base_color.map(f64::sqrt)
+            } else {
+                Color::splat(1.0)
+            };
+            value += tint * (weights[2] * f);
+            pdf += probabilities[2] * lobe_pdf;
+        }
+
+        (value, pdf)
+    }
+}
+
+/// The lobe `u` lands on, and where it landed within it rescaled to [0, 1).
+fn pick_lobe(probabilities: [f64; 4], u: f64) -> (usize, f64) {
+    let mut start = 0.0;
+    for (lobe, &p) in probabilities.iter().enumerate() {
+        let last = probabilities[lobe + 1..].iter().all(|&p| p == 0.0);
+        if p > 0.0 && (u < start + p || last) {
+            return (lobe, ((u - start) / p).clamp(0.0, 1.0 - f64::EPSILON));
+        }
+        start += p;
+    }
+    (0, u)
+}
+
+impl<T> Material<T> for Principled
+where
+    T: Float + From<f64>,
+    Vec3<T>: From<Vec3<f64>>,
+{
+    fn scatter(
+        &self,
+        ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color<T>, Ray<T>)> {
+        let (frame, wo) = shading_frame(ray_in, record);
+        let (u, choice) = (sampler.get_2d(), sampler.get_1d());
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let inside = self.inside(record);
+        let eta = self.glass_lobe().eta(record);
+        let probabilities = self.probabilities(inside);
+        let (lobe, rest) = pick_lobe(probabilities, choice);
+        let smooth = self.distribution.is_smooth();
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let microfacet = |u| {
+            if smooth {
+                normal
+            } else {
+                self.distribution.sample_visible(wo, u)
+            }
+        };
+
+        let (wi, h) = match lobe {
+            0 => {
+                let direction = normal + sample_unit_vector(u);
+                if direction.near_zero() {
+                    (normal, normal)
+                } else {
+                    (direction.unit_vector(), normal)
+                }
+            }
+            1 => {
+                let h = microfacet(u);
+                (reflect_local(wo, h), h)
+            }
+            2 => {
+                let h = microfacet(u);
+                let fresnel = fresnel_dielectric(wo.dot(&h), eta);
+                match refract(wo, h, eta) {
+                    Some(wi) if rest >= fresnel => (wi, h),
+                    _ => (reflect_local(wo, h), h),
+                }
+            }
+            _ => {
+                let h = self.clearcoat_lobe().sample(u);
+                (reflect_local(wo, h), h)
+            }
+        };
+        // microfacets can send light out the wrong side of the surface
+        let wrong_side = if lobe == 2 {
+            (wi.z > 0.0) != (wo.dot(&h) * wi.dot(&h) > 0.0)
+        } else {
+            wi.z < 0.0
+        };
+        if wrong_side || wi.z == 0.0 {
+            return None;
+        }
+        let scattered = Ray::new(record.point, frame.transform(wi).into());
+
+        if smooth && (lobe == 1 || lobe == 2) {
+            // a delta lobe, nothing else reaches exactly this direction
+            let weights = self.weights(inside);
+            let attenuation = if lobe == 1 {
+                fresnel_schlick(wo.z, self.specular_f0()) * weights[1]
+            } else if wi.z < 0.0 {
+                self.base_color.map(f64::sqrt) * weights[2]
+            } else {
+                Color::splat(weights[2])
+            };
+            return Some(((attenuation / probabilities[lobe]).into(), scattered));
+        }
+
+        let (value, pdf) = self.evaluate(wo, wi, inside, eta);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(((value / pdf).into(), scattered))
+    }
+
+    fn scattering_pdf(
+        &self,
+        ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        scattered: &Ray<T>,
+    ) -> Option<T> {
+        let (frame, wo) = shading_frame(ray_in, record);
+        let wi = local_direction(&frame, scattered);
+        let (inside, eta) = (self.inside(record), self.glass_lobe().eta(record));
+        if self.is_specular(wo, wi, inside, eta) {
+            return None;
+        }
+        let (_, pdf) = self.evaluate(wo, wi, inside, eta);
+        Some(<T as From<f64>>::from(pdf))
+    }
+
+    fn eval(&self, ray_in: &Ray<T>, record: &HitRecord<T>, scattered: &Ray<T>) -> Option<Color<T>> {
+        let (frame, wo) = shading_frame(ray_in, record);
+        let wi = local_direction(&frame, scattered);
+        let (inside, eta) = (self.inside(record), self.glass_lobe().eta(record));
+        let (value, _) = self.evaluate(wo, wi, inside, eta);
+        Some(value.into())
+    }
+
+    fn albedo(&self, _record: &HitRecord<T>) -> Option<Color<T>> {
+        Some(self.base_color.into())
+    }
 }
 
 #[derive(Debug)]
 pub struct Dielectric<T> {
     refraction_idx: T,
+    /// Beer-Lambert absorption coefficient per unit distance, per channel.
+    absorption: Option<Color<T>>,
+    /// Used instead of `refraction_idx` when rendering spectrally.
+    dispersion: Option<Ior>,
+    /// With its reflectance from outside and from inside.
+    film: Option<(ThinFilm, [ReflectanceTable; 2])>,
 }
 
 impl<T> Dielectric<T> {
     pub fn new(refraction_idx: T) -> Self {
-        Self { refraction_idx }
+        Self {
+            refraction_idx,
+            absorption: None,
+            dispersion: None,
+            film: None,
+        }
+    }
+
+    /// A soap bubble: nothing but a film with air on both sides.
+    pub fn soap_bubble(thickness: f64) -> Self
+    where
+        T: Float,
+    {
+        Self::new(T::one()).with_thin_film(ThinFilm::soap(thickness))
+    }
+
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self
+    where
+        T: Float,
+    {
+        let inner = self.refraction_idx.to_f64().unwrap();
+        let (air, glass) = (Color::splat(1.0), Color::splat(inner));
+        let none = Color::splat(0.0);
+        let tables = [
+            film.tabulate(1.0, glass, none),
+            film.tabulate(inner, air, none),
+        ];
+        self.film = Some((film, tables));
+        self
+    }
+
+    /// Glass whose index changes with wavelength, splitting white light into
+    /// its colors in spectral renders. RGB renders use the index at the
+    /// sodium D line.
+    pub fn dispersive(ior: Ior) -> Self
+    where
+        T: Float,
+    {
+        Self {
+            dispersion: Some(ior),
+            ..Self::new(T::from(ior.nd()).unwrap())
+        }
+    }
+
+    pub fn with_absorption(mut self, absorption: Color<T>) -> Self {
+        self.absorption = Some(absorption);
+        self
+    }
+
+    /// Tints the glass so light keeps `color` of itself after going through
+    /// `distance` of it, thicker glass being darker. A distance that isn't
+    /// positive leaves the glass clear.
+    pub fn with_transmittance(self, color: Color<T>, distance: T) -> Self
+    where
+        T: Float,
+    {
+        if distance <= T::zero() {
+            return self;
+        }
+        let absorption = color.map(|c| -c.max(T::min_positive_value()).ln() / distance);
+        self.with_absorption(absorption)
     }
 }
 
@@ -103,13 +954,12 @@ impl<T> Material<T> for Dielectric<T>
 where
     T: Float,
 {
-    fn scatter(&self, ray_in: &Ray<T>, record: &HitRecord<T>) -> Option<(Color<T>, Ray<T>)> {
-        let attenuation: Vec3<T> = Color::new(
-            T::from(1.0).unwrap(),
-            T::from(1.0).unwrap(),
-            T::from(1.0).unwrap(),
-        );
-
+    fn scatter(
+        &self,
+        ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color<T>, Ray<T>)> {
         let unit_vector = ray_in.get_direction().unit_vector();
 
         let cos_theta = [
@@ -120,22 +970,633 @@ where
         .fold(T::infinity(), |a, &b| a.min(b));
         let sin_theta = (T::from(1.0).unwrap() - cos_theta * cos_theta).sqrt();
 
+        // each wavelength bends its own way, so only the hero carries on
+        let (refraction_idx, wavelengths) = match (self.dispersion, ray_in.wavelengths()) {
+            (Some(ior), Some(wavelengths)) => (
+                T::from(ior.at(wavelengths.hero())).unwrap(),
+                Some(wavelengths.terminate_secondary()),
+            ),
+            _ => (self.refraction_idx, ray_in.wavelengths()),
+        };
         let ri = if record.front_face() {
-            T::from(1.0).unwrap() / self.refraction_idx
+            T::from(1.0).unwrap() / refraction_idx
         } else {
-            self.refraction_idx
+            refraction_idx
         };
 
         // no solution, so cannot refract in some cases
-        let direction = if ri * sin_theta > T::from(1.0).unwrap()
-            || Self::reflectance(cos_theta, ri) > T::from(rand_float()).unwrap()
-        {
+        let cannot_refract = ri * sin_theta > T::from(1.0).unwrap();
+        let (reflect, weight) = match &self.film {
+            _ if cannot_refract => (true, Color::splat(T::one())),
+            None => {
+                let reflect = Self::reflectance(cos_theta, ri) > T::from(sampler.get_1d()).unwrap();
+                (reflect, Color::splat(T::one()))
+            }
+            Some((film, tables)) => {
+                // the film colors reflection and transmission differently, so
+                // pick by the average and weight each channel back
+                let (outer, inner) = if record.front_face() {
+                    (1.0, refraction_idx.to_f64().unwrap())
+                } else {
+                    (refraction_idx.to_f64().unwrap(), 1.0)
+                };
+                let cos = cos_theta.to_f64().unwrap();
+                let reflectance = match wavelengths {
+                    Some(wavelengths) => {
+                        Color::splat(film.reflectance(cos, outer, inner, 0.0, wavelengths.hero()))
+                    }
+                    None => tables[usize::from(!record.front_face())].at(cos),
+                };
+                let chance = (reflectance.x + reflectance.y + reflectance.z) / 3.0;
+                let reflect = sampler.get_1d() < chance;
+                let weight = if reflect {
+                    reflectance / chance
+                } else {
+                    (Color::splat(1.0) - reflectance) / (1.0 - chance)
+                };
+                (reflect, weight.map(|c| T::from(c).unwrap()))
+            }
+        };
+        let direction = if reflect {
             unit_vector.reflect(record.normal)
         } else {
             unit_vector.refract(record.normal, ri)
         };
+        // interference depends on wavelength too
+        let wavelengths = match self.film {
+            Some(_) => wavelengths.map(|w| w.terminate_secondary()),
+            None => wavelengths,
+        };
+
+        let ray = Ray::new(record.point, direction).with_wavelengths(wavelengths);
+        Some((weight, ray))
+    }
+
+    fn albedo(&self, _record: &HitRecord<T>) -> Option<Color<T>> {
+        Some(Color::splat(T::one()))
+    }
+
+    fn absorption(&self) -> Option<Color<T>> {
+        self.absorption
+    }
+}
+
+/// One of two materials picked at random each time light scatters, the
+/// second `weight` of the time. Good for speckles like the metallic flakes
+/// in paint.
+#[derive(Debug)]
+pub struct Mix<T> {
+    first: Rc<dyn Material<T>>,
+    second: Rc<dyn Material<T>>,
+    weight: f64,
+}
+
+impl<T> Mix<T> {
+    pub fn new(first: Rc<dyn Material<T>>, second: Rc<dyn Material<T>>, weight: f64) -> Mix<T> {
+        Self {
+            first,
+            second,
+            weight: weight.clamp(0.0, 1.0),
+        }
+    }
+
+    fn blend(&self, first: Option<Color<T>>, second: Option<Color<T>>) -> Option<Color<T>>
+    where
+        T: Float,
+    {
+        let weight = T::from(self.weight).unwrap();
+        match (first, second) {
+            (Some(first), Some(second)) => Some(first * (T::one() - weight) + second * weight),
+            (first, second) => first.or(second),
+        }
+    }
+}
+
+impl<T> Material<T> for Mix<T>
+where
+    T: Float,
+{
+    fn scatter(
+        &self,
+        ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color<T>, Ray<T>)> {
+        if sampler.get_1d() < self.weight {
+            self.second.scatter(ray_in, record, sampler)
+        } else {
+            self.first.scatter(ray_in, record, sampler)
+        }
+    }
+
+    fn emitted(&self, ray_in: &Ray<T>, record: &HitRecord<T>) -> Option<Color<T>> {
+        let black = Color::splat(T::zero());
+        match (
+            self.first.emitted(ray_in, record),
+            self.second.emitted(ray_in, record),
+        ) {
+            (None, None) => None,
+            (first, second) => self.blend(first.or(Some(black)), second.or(Some(black))),
+        }
+    }
+
+    fn albedo(&self, record: &HitRecord<T>) -> Option<Color<T>> {
+        self.blend(self.first.albedo(record), self.second.albedo(record))
+    }
+
+    /// Only when both can be evaluated, otherwise it's as specular as the
+    /// more specular of the two.
+    fn scattering_pdf(
+        &self,
+        ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        scattered: &Ray<T>,
+    ) -> Option<T> {
+        let first = self.first.scattering_pdf(ray_in, record, scattered)?;
+        let second = self.second.scattering_pdf(ray_in, record, scattered)?;
+        let weight = T::from(self.weight).unwrap();
+        Some(first * (T::one() - weight) + second * weight)
+    }
+
+    fn eval(&self, ray_in: &Ray<T>, record: &HitRecord<T>, scattered: &Ray<T>) -> Option<Color<T>> {
+        let first = self.first.eval(ray_in, record, scattered)?;
+        let second = self.second.eval(ray_in, record, scattered)?;
+        self.blend(Some(first), Some(second))
+    }
+    fn wavelength_selective(&self) -> bool {
+        self.first.wavelength_selective() || self.second.wavelength_selective()
+    }
+}
+
+/// A clear dielectric coating over any other material, like lacquer or the
+/// clearcoat on car paint. `scatter` follows light through the coating and
+/// bouncing between it and the base until it gets out. There's no closed
+/// form for that, so `eval` takes the coating's reflection plus one pass
+/// through to the base, scaled up for the light the underside of the
+/// coating keeps sending back down as if the base were diffuse.
+#[derive(Debug)]
+pub struct Layered<T> {
+    base: Rc<dyn Material<T>>,
+    ior: f64,
+    /// Only where light first meets the coating, from underneath it's smooth.
+    distribution: Ggx,
+    /// Fraction of each channel getting through the coating straight down.
+    transmittance: Option<Color<f64>>,
+    /// Of light spreading up evenly from the base, the fraction the coating
+    /// sends back down, less what it absorbs on the way up and back.
+    internal: Color<f64>,
+}
+
+impl<T> Layered<T> {
+    pub fn new(base: Rc<dyn Material<T>>, ior: f64) -> Layered<T> {
+        Self {
+            base,
+            ior,
+            distribution: Ggx::from_roughness(0.0),
+            transmittance: None,
+            internal: Self::internal_reflectance(ior, None),
+        }
+    }
+
+    /// Metallic paint: a diffuse base with a fifth of it aluminum flakes,
+    /// under a smooth clearcoat.
+    pub fn car_paint(color: Color<f64>) -> Layered<T>
+    where
+        T: Float + From<f64> + 'static,
+        Vec3<T>: From<Vec3<f64>>,
+    {
+        let paint = Rc::new(Lambertian::new(color.into()));
+        let flakes = Rc::new(Conductor::aluminum(0.4));
+        Self::new(Rc::new(Mix::new(paint, flakes, 0.2)), 1.5)
+    }
+
+    pub fn with_roughness(mut self, roughness: f64) -> Self {
+        self.distribution = Ggx::from_roughness(roughness);
+        self
+    }
+
+    /// Tints the coating, `transmittance` being what's left of light going
+    /// through it once straight down. Slanted paths lose more.
+    pub fn with_absorption(mut self, transmittance: Color<f64>) -> Self {
+        self.transmittance = Some(transmittance);
+        self.internal = Self::internal_reflectance(self.ior, self.transmittance);
+        self
+    }
+
+    fn absorb(&self, direction: Vec3<f64>) -> Color<f64> {
+        Self::absorb_through(self.transmittance, direction.z)
+    }
+
+    fn absorb_through(transmittance: Option<Color<f64>>, cos_theta: f64) -> Color<f64> {
+        match transmittance {
+            Some(transmittance) => transmittance.map(|t| t.powf(1.0 / cos_theta.abs())),
+            None => Color::splat(1.0),
+        }
+    }
+
+    fn internal_reflectance(ior: f64, transmittance: Option<Color<f64>>) -> Color<f64> {
+        // cosine weighted over the hemisphere, at the middle of even steps
+        const STEPS: usize = 64;
+        let mut sum = Color::splat(0.0);
+        for i in 0..STEPS {
+            let cos = (i as f64 + 0.5) / STEPS as f64;
+            let there_and_back = Self::absorb_through(transmittance, cos / 2.0);
+            sum += there_and_back * (fresnel_dielectric(cos, 1.0 / ior) * 2.0 * cos);
+        }
+        sum / STEPS as f64
+    }
+}
+
+impl<T> Layered<T>
+where
+    T: Float + From<f64>,
+    Vec3<T>: From<Vec3<f64>>,
+{
+    /// The BSDF times the cosine towards `wi`, and about the density
+    /// `scatter` picks it with. `None` if the base only has delta lobes.
+    fn evaluate(
+        &self,
+        ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        frame: &Onb<f64>,
+        (wo, wi): (Vec3<f64>, Vec3<f64>),
+    ) -> Option<(Color<f64>, f64)> {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Some((Color::splat(0.0), 0.0));
+        }
+        let to_world = |w: Vec3<f64>| -> Vec3<T> { frame.transform(w).into() };
+        let fresnel_in = fresnel_dielectric(wo.z, self.ior);
+
+        // off the top, when it isn't a mirror left to scatter
+        let (mut value, mut pdf) = (Color::splat(0.0), 0.0);
+        if !self.distribution.is_smooth() {
+            let h = (wo + wi).unit_vector();
+            let fresnel = fresnel_dielectric(wo.dot(&h), self.ior);
+            let d = self.distribution.d(h);
+            value += Color::splat(fresnel * d * self.distribution.g(wo, wi) / (4.0 * wo.z));
+            pdf += fresnel * self.distribution.pdf_visible(wo, h) / (4.0 * wo.dot(&h));
+        }
+
+        // through the coating to the base and back out, both ways bent
+        // along the normal
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let (Some(down), Some(back)) =
+            (refract(wo, normal, self.ior), refract(wi, normal, self.ior))
+        else {
+            return Some((value, pdf));
+        };
+        let up = back * -1.0;
+        let inside = Ray::new(record.point - to_world(down), to_world(down))
+            .with_wavelengths(ray_in.wavelengths());
+        let towards = Ray::new(record.point, to_world(up));
+        let base = self.base.eval(&inside, record, &towards)?;
+        let base = base.map(|c| c.to_f64().unwrap_or(0.0));
+
+        let albedo = self.base.albedo(record).unwrap_or(Color::splat(T::zero()));
+        let albedo = albedo.map(|c| c.to_f64().unwrap_or(0.0));
+        let trapped = (albedo.mul_vec3(self.internal)).map(|c| 1.0 / (1.0 - c.min(0.999)));
+        let fresnel_out = fresnel_dielectric(wi.z, self.ior);
+        let through = (1.0 - fresnel_in) * (1.0 - fresnel_out) / (self.ior * self.ior);
+        let value = value
+            + (base * (through * wi.z / up.z))
+                .mul_vec3(self.absorb(down))
+                .mul_vec3(self.absorb(up))
+                .mul_vec3(trapped);
+        Some((value, pdf + (1.0 - fresnel_in) * wi.z / PI))
+    }
+
+    /// The mirror direction of a smooth coating, which `eval` leaves out.
+    fn is_specular(&self, wo: Vec3<f64>, wi: Vec3<f64>) -> bool {
+        let mirror = reflect_local(wo, Vec3::new(0.0, 0.0, 1.0));
+        self.distribution.is_smooth() && (mirror - wi).length() < 1e-6
+    }
+}
+
+impl<T> Material<T> for Layered<T>
+where
+    T: Float + From<f64>,
+    Vec3<T>: From<Vec3<f64>>,
+{
+    fn scatter(
+        &self,
+        ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color<T>, Ray<T>)> {
+        // light bouncing around inside after that many goes on by chance
+        const ROULETTE_AFTER: usize = 3;
+
+        let (frame, wo) = shading_frame(ray_in, record);
+        let (u, choice) = (sampler.get_2d(), sampler.get_1d());
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let to_world = |w: Vec3<f64>| -> Vec3<T> { frame.transform(w).into() };
+        let smooth = self.distribution.is_smooth();
+        let h = if smooth {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            self.distribution.sample_visible(wo, u)
+        };
+
+        // off the top of the coating, by Fresnel's odds
+        let fresnel = fresnel_dielectric(wo.dot(&h), self.ior);
+        let Some(down) = refract(wo, h, self.ior).filter(|_| choice >= fresnel) else {
+            let wi = reflect_local(wo, h);
+            if wi.z <= 0.0 {
+                return None;
+            }
+            let weight = if smooth {
+                1.0
+            } else {
+                self.distribution.g(wo, wi) / self.distribution.g1(wo)
+            };
+            let scattered = Ray::new(record.point, to_world(wi));
+            return Some((
+                Color::splat(<T as From<f64>>::from(weight)),
+                scattered.with_wavelengths(ray_in.wavelengths()),
+            ));
+        };
+        if down.z >= 0.0 {
+            return None;
+        }
+
+        let mut throughput = if smooth {
+            Color::splat(1.0)
+        } else {
+            Color::splat(self.distribution.g(wo, down) / self.distribution.g1(wo))
+        };
+        let mut direction = down;
+        let mut wavelengths = ray_in.wavelengths();
+        let mut bounces = 0;
+        loop {
+            // the base sees light coming down through the coating
+            throughput = throughput.mul_vec3(self.absorb(direction));
+            let inside = Ray::new(record.point - to_world(direction), to_world(direction))
+                .with_wavelengths(wavelengths);
+            let (attenuation, scattered) = self.base.scatter(&inside, record, sampler)?;
+            wavelengths = scattered.wavelengths().or(wavelengths);
+            throughput = throughput.mul_vec3(attenuation.map(|c| c.to_f64().unwrap_or(0.0)));
+
+            let up = local_direction(&frame, &scattered);
+            if up.z <= 0.0 {
+                return None;
+            }
+            throughput = throughput.mul_vec3(self.absorb(up));
+
+            // out through the coating, or back down off its underside
+            let exit = refract(up * -1.0, Vec3::new(0.0, 0.0, -1.0), 1.0 / self.ior);
+            match exit {
+                Some(wi) if sampler.get_1d() >= fresnel_dielectric(up.z, 1.0 / self.ior) => {
+                    let scattered = Ray::new(record.point, to_world(wi));
+                    return Some((throughput.into(), scattered.with_wavelengths(wavelengths)));
+                }
+                _ => direction = Vec3::new(up.x, up.y, -up.z),
+            }
+
+            bounces += 1;
+            if bounces >= ROULETTE_AFTER {
+                let survive = throughput.x.max(throughput.y).max(throughput.z).min(0.95);
+                if sampler.get_1d() >= survive {
+                    return None;
+                }
+                throughput /= survive;
+            }
+        }
+    }
+
+    fn scattering_pdf(
+        &self,
+        ray_in: &Ray<T>,
+        record: &HitRecord<T>,
+        scattered: &Ray<T>,
+    ) -> Option<T> {
+        let (frame, wo) = shading_frame(ray_in, record);
+        let wi = local_direction(&frame, scattered);
+        if self.is_specular(wo, wi) {
+            return None;
+        }
+        let (_, pdf) = self.evaluate(ray_in, record, &frame, (wo, wi))?;
+        Some(<T as From<f64>>::from(pdf))
+    }
+
+    fn eval(&self, ray_in: &Ray<T>, record: &HitRecord<T>, scattered: &Ray<T>) -> Option<Color<T>> {
+        let (frame, wo) = shading_frame(ray_in, record);
+        let wi = local_direction(&frame, scattered);
+        let (value, _) = self.evaluate(ray_in, record, &frame, (wo, wi))?;
+        Some(value.into())
+    }
+
+    /// The base as seen through the coating straight on, there and back.
+    fn albedo(&self, record: &HitRecord<T>) -> Option<Color<T>> {
+        let straight = self.absorb(Vec3::new(0.0, 0.0, 1.0));
+        let tint: Color<T> = straight.mul_vec3(straight).into();
+        Some(self.base.albedo(record)?.mul_vec3(tint))
+    }
+    fn wavelength_selective(&self) -> bool {
+        self.base.wavelength_selective()
+    }
+}
+
+#[derive(Debug)]
+pub struct DiffuseLight<T> {
+    emit: Color<T>,
+}
+
+impl<T> DiffuseLight<T> {
+    pub fn new(emit: Color<T>) -> DiffuseLight<T> {
+        Self { emit }
+    }
+}
+
+impl<T> Material<T> for DiffuseLight<T>
+where
+    T: Float,
+{
+    fn scatter(
+        &self,
+        _ray_in: &Ray<T>,
+        _record: &HitRecord<T>,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<(Color<T>, Ray<T>)> {
+        None
+    }
+
+    fn emitted(&self, _ray_in: &Ray<T>, _record: &HitRecord<T>) -> Option<Color<T>> {
+        Some(self.emit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sampler::SamplerKind, utils::Point};
+
+    /// A hit at the origin on a surface facing +z, for a ray coming in
+    /// along `direction`.
+    fn hit(material: Rc<dyn Material<f64>>, direction: Vec3<f64>) -> (Ray<f64>, HitRecord<f64>) {
+        let ray = Ray::new(direction * -1.0, direction);
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let record = HitRecord::new(Point::splat(0.0), normal, 1.0, (0.0, 0.0), &ray, material);
+        (ray, record)
+    }
+
+    /// Scatters `samples` rays coming in along `direction` off `material`.
+    fn for_each_scatter(
+        material: Rc<dyn Material<f64>>,
+        direction: Vec3<f64>,
+        samples: u32,
+        mut check: impl FnMut(&Ray<f64>, &HitRecord<f64>, Option<(Color<f64>, Ray<f64>)>),
+    ) {
+        let mut sampler = SamplerKind::Independent.build(samples, 3);
+        for i in 0..samples {
+            sampler.start_pixel_sample((0, 0), i);
+            let (ray, record) = hit(material.clone(), direction);
+            check(
+                &ray,
+                &record,
+                material.scatter(&ray, &record, sampler.as_mut()),
+            );
+        }
+    }
+
+    #[test]
+    fn smooth_principled_glass_loses_nothing() {
+        let glass = Rc::new(Principled::glass(1.5, 0.0));
+        let direction = Vec3::new(0.5, 0.0, -1.0).unit_vector();
+        for_each_scatter(glass, direction, 256, |_, _, scattered| {
+            let (attenuation, _) = scattered.unwrap();
+            assert!((attenuation - Color::splat(1.0)).length() < 1e-12);
+        });
+    }
+
+    #[test]
+    fn principled_eval_matches_what_it_scatters() {
+        let material: Rc<dyn Material<f64>> = Rc::new(
+            Principled::new(Color::new(0.8, 0.4, 0.2))
+                .with_roughness(0.4)
+                .with_clearcoat(0.5, 0.8)
+                .with_sheen(0.5, 0.5)
+                .with_transmission(0.3, 1.4),
+        );
+        let direction = Vec3::new(0.4, 0.3, -1.0).unit_vector();
+        for_each_scatter(
+            material.clone(),
+            direction,
+            256,
+            |ray, record, scattered| {
+                if let Some((attenuation, scattered)) = scattered {
+                    let pdf = material.scattering_pdf(ray, record, &scattered).unwrap();
+                    let eval = material.eval(ray, record, &scattered).unwrap();
+                    assert!((attenuation - eval / pdf).length() < 1e-9);
+                }
+            },
+        );
+    }
+
+    #[test]
+    fn smooth_principled_leaves_the_mirror_to_scatter() {
+        let material: Rc<dyn Material<f64>> =
+            Rc::new(Principled::new(Color::splat(0.5)).with_roughness(0.0));
+        let direction = Vec3::new(0.6, 0.0, -0.8);
+        let (ray, record) = hit(material.clone(), direction);
+        let mirror = Ray::new(Point::splat(0.0), Vec3::new(0.6, 0.0, 0.8));
+        assert!(material.scattering_pdf(&ray, &record, &mirror).is_none());
+        // the diffuse lobe is still there for lights to be sampled with
+        let up = Ray::new(Point::splat(0.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(material.eval(&ray, &record, &up).unwrap().x > 0.0);
+    }
+
+    #[test]
+    fn transmittance_over_no_distance_stays_clear() {
+        let glass = Dielectric::new(1.5).with_transmittance(Color::splat(0.5), 0.0);
+        assert!(Material::<f64>::absorption(&glass).is_none());
+
+        let glass = Dielectric::new(1.5).with_transmittance(Color::new(0.5, 1.0, 0.0), 2.0);
+        let absorption = Material::<f64>::absorption(&glass).unwrap();
+        assert!((absorption.x - 2.0_f64.ln() / 2.0).abs() < 1e-12);
+        assert_eq!(absorption.y, 0.0);
+        assert!(absorption.z.is_finite());
+    }
 
-        let ray = Ray::new(record.point, direction);
-        Some((attenuation, ray))
+    #[test]
+    fn coated_white_loses_nothing() {
+        let base = Rc::new(Lambertian::new(Color::splat(1.0)));
+        let coated: Rc<dyn Material<f64>> = Rc::new(Layered::new(base, 1.5));
+        let direction = Vec3::new(0.5, 0.0, -1.0).unit_vector();
+        let samples = 20_000;
+        let mut sum = Color::splat(0.0);
+        for_each_scatter(coated, direction, samples, |_, _, scattered| {
+            if let Some((attenuation, _)) = scattered {
+                sum += attenuation / samples as f64;
+            }
+        });
+        assert!((sum - Color::splat(1.0)).length() < 0.02, "{sum:?}");
+    }
+
+    #[test]
+    fn layered_eval_matches_what_it_scatters() {
+        let base = Rc::new(Lambertian::new(Color::new(0.6, 0.4, 0.2)));
+        let coated: Rc<dyn Material<f64>> =
+            Rc::new(Layered::new(base, 1.5).with_absorption(Color::new(0.9, 0.8, 0.9)));
+        let direction = Vec3::new(0.5, 0.0, -1.0).unit_vector();
+
+        // what scatter sends anywhere but the mirror, and how high up
+        let samples = 40_000;
+        let (mut total, mut raised) = (Color::splat(0.0), Color::splat(0.0));
+        for_each_scatter(
+            coated.clone(),
+            direction,
+            samples,
+            |ray, record, scattered| {
+                let Some((attenuation, scattered)) = scattered else {
+                    return;
+                };
+                if coated.scattering_pdf(ray, record, &scattered).is_some() {
+                    total += attenuation / samples as f64;
+                    raised +=
+                        attenuation * scattered.get_direction().unit_vector().z / samples as f64;
+                }
+            },
+        );
+
+        // the same from eval, over an even grid of cosines and angles
+        let (ray, record) = hit(coated.clone(), direction);
+        let steps = 200;
+        let cell = 2.0 * PI / (steps * steps) as f64;
+        let (mut expected_total, mut expected_raised) = (Color::splat(0.0), Color::splat(0.0));
+        for i in 0..steps {
+            for j in 0..steps {
+                let cos = (i as f64 + 0.5) / steps as f64;
+                let phi = 2.0 * PI * (j as f64 + 0.5) / steps as f64;
+                let sin = (1.0 - cos * cos).sqrt();
+                let wi = Vec3::new(sin * phi.cos(), sin * phi.sin(), cos);
+                let eval = coated.eval(&ray, &record, &Ray::new(Point::splat(0.0), wi));
+                let eval = eval.unwrap() * cell;
+                expected_total += eval;
+                expected_raised += eval * cos;
+            }
+        }
+
+        assert!(
+            (total - expected_total).length() < 0.01,
+            "{total:?} {expected_total:?}"
+        );
+        assert!(
+            (raised - expected_raised).length() < 0.01,
+            "{raised:?} {expected_raised:?}"
+        );
+    }
+
+    #[test]
+    fn coating_tints_the_albedo() {
+        let base = Rc::new(Lambertian::new(Color::splat(0.5)));
+        let coated = Layered::new(base, 1.5).with_absorption(Color::new(1.0, 0.5, 0.5));
+        let (_, record) = hit(
+            Rc::new(Lambertian::new(Color::splat(0.0))),
+            Vec3::splat(-1.0),
+        );
+        let albedo = Material::<f64>::albedo(&coated, &record).unwrap();
+        assert!((albedo - Color::new(0.5, 0.125, 0.125)).length() < 1e-12);
     }
 }
@@ -0,0 +1,288 @@
+use crate::utils::{Color, Vec3, PI};
+
+/// The GGX (Trowbridge-Reitz) distribution of microfacet normals, with
+/// Smith masking-shadowing. Directions are in the local shading frame, the
+/// macro surface normal along +z.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ggx {
+    pub alpha: f64,
+}
+
+impl Ggx {
+    /// From the perceptual roughness in [0, 1], squared like most renderers
+    /// do so the middle of the range looks like the middle.
+    pub fn from_roughness(roughness: f64) -> Ggx {
+        let roughness = roughness.clamp(0.0, 1.0);
+        Self {
+            alpha: (roughness * roughness).max(1e-4),
+        }
+    }
+
+    /// Smooth enough to treat as a mirror, where sampling lights is hopeless.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < 1e-3
+    }
+
+    /// Density of microfacet normal `h` per unit projected area.
+    pub fn d(&self, h: Vec3<f64>) -> f64 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let denominator = h.z * h.z * (a2 - 1.0) + 1.0;
+        a2 / (PI * denominator * denominator)
+    }
+
+    fn lambda(&self, w: Vec3<f64>) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0 {
+            return f64::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0) / 2.0
+    }
+
+    /// Fraction of the microfacets facing `h` that are visible from `w`.
+    pub fn g1(&self, w: Vec3<f64>) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Height correlated masking-shadowing for a pair of directions.
+    pub fn g(&self, wo: Vec3<f64>, wi: Vec3<f64>) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Microfacet normal distributed like the ones visible from `wo`, after
+    /// Heitz, "Sampling the GGX Distribution of Visible Normals" (2018).
+    pub fn sample_visible(&self, wo: Vec3<f64>, (u1, u2): (f64, f64)) -> Vec3<f64> {
+        // stretch to the hemisphere configuration
+        let vh = Vec3::new(self.alpha * wo.x, self.alpha * wo.y, wo.z).unit_vector();
+        let length2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if length2 > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / length2.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(&t1);
+
+        // a disk, squashed where part of it is hidden by the hemisphere
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        Vec3::new(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(1e-6)).unit_vector()
+    }
+
+    /// Density `sample_visible` picks `h` with.
+    pub fn pdf_visible(&self, wo: Vec3<f64>, h: Vec3<f64>) -> f64 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(&h).max(0.0) * self.d(h) / wo.z
+    }
+}
+
+/// The Berry (GTR1) distribution Disney uses for clearcoat, with a longer
+/// tail than GGX. Masking uses GGX with a fixed roughness, like theirs.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Gtr1 {
+    pub alpha: f64,
+}
+
+impl Gtr1 {
+    pub fn d(&self, h: Vec3<f64>) -> f64 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        if self.alpha >= 1.0 {
+            return 1.0 / PI;
+        }
+        let a2 = self.alpha * self.alpha;
+        (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * h.z * h.z))
+    }
+
+    /// Microfacet normal with density `d(h) * h.z`.
+    pub fn sample(&self, (u1, u2): (f64, f64)) -> Vec3<f64> {
+        let a2 = self.alpha * self.alpha;
+        let cos2 = if self.alpha >= 1.0 {
+            1.0 - u1
+        } else {
+            (1.0 - a2.powf(1.0 - u1)) / (1.0 - a2)
+        };
+        let cos_theta = cos2.clamp(0.0, 1.0).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let (sin_phi, cos_phi) = (2.0 * PI * u2).sin_cos();
+        Vec3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)
+    }
+
+    pub fn pdf(&self, h: Vec3<f64>) -> f64 {
+        self.d(h) * h.z.max(0.0)
+    }
+
+    pub fn g(&self, wo: Vec3<f64>, wi: Vec3<f64>) -> f64 {
+        let masking = Ggx { alpha: 0.25 };
+        masking.g1(wo) * masking.g1(wi)
+    }
+}
+
+/// Schlick's approximation to the Fresnel reflectance rising from `f0`.
+pub fn fresnel_schlick(cos_theta: f64, f0: Color<f64>) -> Color<f64> {
+    let weight = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+    f0 + (Color::splat(1.0) - f0) * weight
+}
+
+/// Fresnel reflectance of a conductor with complex index of refraction
+/// `eta + i k` per channel, seen from air at `cos_theta`.
+pub fn fresnel_conductor(cos_theta: f64, eta: Color<f64>, k: Color<f64>) -> Color<f64> {
+    let cos2 = cos_theta.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let channel = |eta: f64, k: f64| {
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_theta * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rp + rs)
+    };
+    Color::new(
+        channel(eta.x, k.x),
+        channel(eta.y, k.y),
+        channel(eta.z, k.z),
+    )
+}
+
+/// Fresnel reflectance of a boundary between dielectrics, `eta` being the
+/// index on the far side over the one on the near side and `cos_theta`
+/// measured on the near side. 1 past the critical angle.
+pub fn fresnel_dielectric(cos_theta: f64, eta: f64) -> f64 {
+    let cos_i = cos_theta.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+/// Refracts `wo` through a microfacet with normal `h` into a medium `eta`
+/// times as dense. `None` for total internal reflection.
+pub fn refract(wo: Vec3<f64>, h: Vec3<f64>, eta: f64) -> Option<Vec3<f64>> {
+    let cos_i = wo.dot(&h);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(wo * (-1.0 / eta) + h * (cos_i / eta - cos_t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Midpoint rule over the hemisphere, in cos theta and phi.
+    fn integrate_hemisphere(f: impl Fn(Vec3<f64>) -> f64) -> f64 {
+        const STEPS: usize = 800;
+        let step = 1.0 / STEPS as f64;
+        let mut sum = 0.0;
+        for i in 0..STEPS {
+            let cos_theta = (i as f64 + 0.5) * step;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..STEPS {
+                let phi = 2.0 * PI * (j as f64 + 0.5) * step;
+                sum += f(Vec3::new(
+                    sin_theta * phi.cos(),
+                    sin_theta * phi.sin(),
+                    cos_theta,
+                ));
+            }
+        }
+        sum * step * 2.0 * PI * step
+    }
+
+    #[test]
+    fn ggx_projected_area_is_one() {
+        for roughness in [0.5, 0.8, 1.0] {
+            let ggx = Ggx::from_roughness(roughness);
+            let area = integrate_hemisphere(|h| ggx.d(h) * h.z);
+            assert!((area - 1.0).abs() < 1e-3, "{roughness}: {area}");
+        }
+    }
+
+    #[test]
+    fn visible_normals_are_a_distribution() {
+        // the weak white furnace test: every bit of the surface seen from wo
+        // is covered by exactly one visible microfacet
+        let ggx = Ggx::from_roughness(0.7);
+        for cos_o in [1.0f64, 0.7, 0.3] {
+            let wo = Vec3::new((1.0 - cos_o * cos_o).sqrt(), 0.0, cos_o);
+            let total = integrate_hemisphere(|h| ggx.pdf_visible(wo, h));
+            assert!((total - 1.0).abs() < 1e-3, "{cos_o}: {total}");
+        }
+    }
+
+    #[test]
+    fn sampled_visible_normals_face_the_viewer() {
+        let ggx = Ggx::from_roughness(0.5);
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        for i in 0..16 {
+            for j in 0..16 {
+                let u = ((i as f64 + 0.5) / 16.0, (j as f64 + 0.5) / 16.0);
+                let h = ggx.sample_visible(wo, u);
+                assert!((h.length() - 1.0).abs() < 1e-9);
+                assert!(h.z > 0.0 && wo.dot(&h) >= -1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn conductor_fresnel_known_values() {
+        // gold's red channel head on: ((n - 1)² + k²) / ((n + 1)² + k²)
+        let (eta, k) = (Color::splat(0.143), Color::splat(3.983));
+        let expected = ((0.143f64 - 1.0).powi(2) + 3.983f64.powi(2))
+            / ((0.143f64 + 1.0).powi(2) + 3.983f64.powi(2));
+        assert!((fresnel_conductor(1.0, eta, k).x - expected).abs() < 1e-9);
+        assert!((fresnel_conductor(0.0, eta, k).x - 1.0).abs() < 1e-9);
+
+        let f0 = Color::new(0.9, 0.5, 0.1);
+        assert_eq!(fresnel_schlick(1.0, f0).to_array(), f0.to_array());
+        assert_eq!(fresnel_schlick(0.0, f0).to_array(), [1.0; 3]);
+    }
+
+    #[test]
+    fn dielectric_fresnel_known_values() {
+        // ((n - 1) / (n + 1))² head on, all of it at grazing
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-12);
+        assert!((fresnel_dielectric(1.0, 1.0 / 1.5) - 0.04).abs() < 1e-12);
+        assert!((fresnel_dielectric(0.0, 1.5) - 1.0).abs() < 1e-12);
+        // from inside glass past the critical angle, about 41.8°
+        assert_eq!(fresnel_dielectric(45f64.to_radians().cos(), 1.0 / 1.5), 1.0);
+        assert!(fresnel_dielectric(40f64.to_radians().cos(), 1.0 / 1.5) < 1.0);
+    }
+
+    #[test]
+    fn refraction_follows_snell_and_reverses() {
+        let eta = 1.5;
+        let h = Vec3::new(0.0, 0.0, 1.0);
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        let wi = refract(wo, h, eta).unwrap();
+        assert!((wi.length() - 1.0).abs() < 1e-12);
+        assert!(wi.z < 0.0);
+        // sin on the far side is sin on the near side over eta, mirrored
+        assert!((wi.x + 0.6 / eta).abs() < 1e-12);
+
+        // and from the far side straight back out along wo
+        let back = refract(wi, h * -1.0, 1.0 / eta).unwrap();
+        assert!((back - wo).length() < 1e-12);
+        assert!(refract(Vec3::new(0.8, 0.0, 0.6), h, 1.0 / eta).is_none());
+    }
+}
@@ -0,0 +1,139 @@
+/// A camera described the way a photographer would. The scene is taken to
+/// be modelled in metres, while the sensor and lens are measured in
+/// millimetres like on a real camera.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PhysicalCamera {
+    /// In millimetres.
+    pub sensor_width: f64,
+    /// In millimetres.
+    pub sensor_height: f64,
+    /// In millimetres.
+    pub focal_length: f64,
+    pub f_number: f64,
+    /// In metres, the scene's units.
+    pub focus_distance: f64,
+    /// Shutter speed in seconds.
+    pub shutter: f64,
+    pub iso: f64,
+}
+
+impl Default for PhysicalCamera {
+    /// A full frame body with a 50mm lens, exposed by the sunny 16 rule.
+    fn default() -> Self {
+        Self {
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+            focal_length: 50.0,
+            f_number: 16.0,
+            focus_distance: 10.0,
+            shutter: 1.0 / 100.0,
+            iso: 100.0,
+        }
+    }
+}
+
+impl PhysicalCamera {
+    pub fn new(focal_length: f64, f_number: f64, focus_distance: f64) -> PhysicalCamera {
+        Self {
+            focal_length,
+            f_number,
+            focus_distance,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_sensor(mut self, width: f64, height: f64) -> Self {
+        self.sensor_width = width;
+        self.sensor_height = height;
+        self
+    }
+
+    pub fn with_exposure(mut self, shutter: f64, iso: f64) -> Self {
+        self.shutter = shutter;
+        self.iso = iso;
+        self
+    }
+
+    /// Vertical field of view in degrees for an image of `aspect_ratio`.
+    /// The image is fit inside the sensor, so whichever side is relatively
+    /// longer spans the sensor and the other is cropped.
+    pub fn vfov(&self, aspect_ratio: f64) -> f64 {
+        let height = self.sensor_height.min(self.sensor_width / aspect_ratio);
+        2.0 * (height / (2.0 * self.focal_length)).atan().to_degrees()
+    }
+
+    /// Radius of the entrance pupil, in metres.
+    pub fn aperture_radius(&self) -> f64 {
+        self.focal_length / 1000.0 / (2.0 * self.f_number)
+    }
+
+    /// The angle the aperture spans seen from the focus distance, in the
+    /// degrees the thin lens camera wants.
+    pub fn defocus_angle(&self) -> f64 {
+        2.0 * (self.aperture_radius() / self.focus_distance)
+            .atan()
+            .to_degrees()
+    }
+
+    /// Exposure in stops, calibrated by the sunny 16 rule: f/16 for 1/ISO
+    /// seconds gives 0, the right exposure for things lit as brightly as the
+    /// sky's radiance of 1.
+    pub fn exposure(&self) -> f64 {
+        (self.shutter * self.iso * 256.0 / (self.f_number * self.f_number)).log2()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lens_and_sensor_give_the_field_of_view() {
+        let camera = PhysicalCamera::new(50.0, 2.0, 5.0);
+        let expected = 2.0 * (12.0_f64 / 50.0).atan().to_degrees();
+        assert!((camera.vfov(1.5) - expected).abs() < 1e-12);
+        // a square image is cropped from the sides, so it's no taller
+        assert!((camera.vfov(1.0) - expected).abs() < 1e-12);
+        // a wide one from the top and bottom
+        let expected = 2.0 * (9.0_f64 / 50.0).atan().to_degrees();
+        assert!((camera.vfov(2.0) - expected).abs() < 1e-12);
+
+        let wide = camera.with_sensor(24.0, 16.0);
+        assert!(wide.vfov(1.5) < camera.vfov(1.5));
+    }
+
+    #[test]
+    fn f_number_sets_the_defocus_angle() {
+        let camera = PhysicalCamera::new(50.0, 2.0, 5.0);
+        assert!((camera.aperture_radius() - 0.0125).abs() < 1e-15);
+        let expected = 2.0 * (0.0125_f64 / 5.0).atan().to_degrees();
+        assert!((camera.defocus_angle() - expected).abs() < 1e-12);
+
+        let stopped_down = PhysicalCamera::new(50.0, 8.0, 5.0);
+        let ratio = camera.defocus_angle() / stopped_down.defocus_angle();
+        assert!((ratio - 4.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn exposure_follows_iso_shutter_and_f_number() {
+        // sunny 16 is the zero point
+        let sunny = PhysicalCamera::new(50.0, 16.0, 5.0).with_exposure(1.0 / 100.0, 100.0);
+        assert!(sunny.exposure().abs() < 1e-12);
+
+        let cases = [
+            (16.0, 1.0 / 100.0, 200.0, 1.0),
+            (8.0, 1.0 / 100.0, 100.0, 2.0),
+            (16.0, 1.0 / 400.0, 100.0, -2.0),
+            (
+                5.6,
+                1.0 / 50.0,
+                400.0,
+                (400.0 / 50.0 * 256.0 / (5.6 * 5.6_f64)).log2(),
+            ),
+        ];
+        for (f_number, shutter, iso, stops) in cases {
+            let camera = PhysicalCamera::new(50.0, f_number, 5.0).with_exposure(shutter, iso);
+            assert!((camera.exposure() - stops).abs() < 1e-12, "{camera:?}");
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::path::{Path, PathBuf};
+
+/// Renders in passes over the whole image instead of finishing one pixel at
+/// a time, so a usable (if noisy) image exists long before the end.
+#[derive(Debug, Clone)]
+pub struct Progressive {
+    samples_per_pass: u32,
+    /// Write the current image to `snapshot` every this many passes.
+    snapshot_interval: u32,
+    snapshot: Option<PathBuf>,
+}
+
+impl Progressive {
+    pub fn new(samples_per_pass: u32) -> Progressive {
+        Self {
+            samples_per_pass: samples_per_pass.max(1),
+            snapshot_interval: 1,
+            snapshot: None,
+        }
+    }
+
+    /// At least one, however many were asked for.
+    pub fn samples_per_pass(&self) -> u32 {
+        self.samples_per_pass
+    }
+
+    pub fn with_snapshots(mut self, path: impl Into<PathBuf>, interval: u32) -> Self {
+        self.snapshot = Some(path.into());
+        self.snapshot_interval = interval.max(1);
+        self
+    }
+
+    pub fn snapshot(&self) -> Option<&Path> {
+        self.snapshot.as_deref()
+    }
+
+    pub fn snapshot_interval(&self) -> u32 {
+        self.snapshot_interval
+    }
+
+    /// Where to write the snapshot after `pass`, if this pass gets one.
+    pub fn snapshot_path(&self, pass: u32) -> Option<&Path> {
+        self.snapshot
+            .as_deref()
+            .filter(|_| pass.is_multiple_of(self.snapshot_interval))
+    }
+}
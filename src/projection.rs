@@ -0,0 +1,158 @@
+use crate::utils::{Vec3, PI};
+
+/// How positions on the image map to rays leaving the camera.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Projection {
+    /// Pinhole or thin lens, with the camera's vertical field of view.
+    #[default]
+    Perspective,
+    /// Parallel rays along the view direction from a plane `view_width`
+    /// world units across, for elevations and other technical drawings.
+    /// There is no depth of field.
+    Orthographic { view_width: f64 },
+    /// Longitude across, latitude down, covering the whole sphere with the
+    /// view direction in the middle. Wants a 2:1 image.
+    Equirectangular,
+    /// A circular fisheye image of `fov` degrees, inscribed in the image.
+    /// Pixels outside the circle stay black.
+    Fisheye { fov: f64, mapping: FisheyeMapping },
+    /// The six faces of a cube side by side in the order +X, -X, +Y, -Y, +Z,
+    /// -Z, each oriented like an OpenGL cube map. The axes are the camera's:
+    /// +X right, +Y up and +Z behind, so -Z looks at `lookat`. Wants a 6:1
+    /// image.
+    Cubemap,
+}
+
+/// How the angle from the view direction maps to distance from the middle
+/// of a fisheye image.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum FisheyeMapping {
+    /// Distance proportional to the angle.
+    #[default]
+    Equidistant,
+    /// Equal areas of the image cover equal solid angles.
+    Equisolid,
+}
+
+impl Projection {
+    /// Direction of the ray through `(s, t)`, both in [0, 1] from the top
+    /// left, in the camera's frame (x right, y up, z behind) for the
+    /// panoramic projections. `None` where the projection has no ray, and for
+    /// the perspective and orthographic ones, which depend on more than the
+    /// image position.
+    pub fn direction(&self, s: f64, t: f64, aspect_ratio: f64) -> Option<Vec3<f64>> {
+        match *self {
+            Projection::Perspective | Projection::Orthographic { .. } => None,
+            Projection::Equirectangular => {
+                let phi = (s - 0.5) * 2.0 * PI;
+                let theta = t * PI;
+                Some(Vec3::new(
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                    -theta.sin() * phi.cos(),
+                ))
+            }
+            Projection::Fisheye { fov, mapping } => {
+                // the circle fits the shorter side
+                let (mut x, mut y) = (2.0 * s - 1.0, 1.0 - 2.0 * t);
+                if aspect_ratio > 1.0 {
+                    x *= aspect_ratio;
+                } else {
+                    y /= aspect_ratio;
+                }
+                let r = x.hypot(y);
+                if r > 1.0 {
+                    return None;
+                }
+
+                let max_theta = fov.to_radians() / 2.0;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * max_theta,
+                    FisheyeMapping::Equisolid => {
+                        2.0 * (r * (max_theta / 2.0).sin()).clamp(-1.0, 1.0).asin()
+                    }
+                };
+                let phi = y.atan2(x);
+                Some(Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    -theta.cos(),
+                ))
+            }
+            Projection::Cubemap => {
+                let face = ((s * 6.0) as usize).min(5);
+                let sc = (s * 6.0 - face as f64) * 2.0 - 1.0;
+                let tc = t * 2.0 - 1.0;
+                let (x, y, z) = match face {
+                    0 => (1.0, -tc, -sc),
+                    1 => (-1.0, -tc, sc),
+                    2 => (sc, 1.0, tc),
+                    3 => (sc, -1.0, -tc),
+                    4 => (sc, -tc, 1.0),
+                    _ => (-sc, -tc, -1.0),
+                };
+                Some(Vec3::new(x, y, z).unit_vector())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(v: Vec3<f64>, expected: [f64; 3]) {
+        let v = v.to_array();
+        for (a, b) in v.iter().zip(expected) {
+            assert!((a - b).abs() < 1e-9, "{v:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn equirectangular_covers_the_sphere() {
+        let project = |s, t| Projection::Equirectangular.direction(s, t, 2.0).unwrap();
+        assert_near(project(0.5, 0.5), [0.0, 0.0, -1.0]);
+        assert_near(project(0.75, 0.5), [1.0, 0.0, 0.0]);
+        assert_near(project(0.0, 0.5), [0.0, 0.0, 1.0]);
+        assert_near(project(0.3, 0.0), [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn fisheye_edge_is_half_the_fov() {
+        for mapping in [FisheyeMapping::Equidistant, FisheyeMapping::Equisolid] {
+            let fisheye = Projection::Fisheye {
+                fov: 180.0,
+                mapping,
+            };
+            assert_near(fisheye.direction(0.5, 0.5, 1.0).unwrap(), [0.0, 0.0, -1.0]);
+            assert_near(fisheye.direction(1.0, 0.5, 1.0).unwrap(), [1.0, 0.0, 0.0]);
+            assert_near(fisheye.direction(0.5, 0.0, 1.0).unwrap(), [0.0, 1.0, 0.0]);
+            assert!(fisheye.direction(0.0, 0.0, 1.0).is_none());
+        }
+
+        // a quarter of the way out is a quarter of the angle for equidistant
+        let fisheye = Projection::Fisheye {
+            fov: 180.0,
+            mapping: FisheyeMapping::Equidistant,
+        };
+        let d = fisheye.direction(0.625, 0.5, 1.0).unwrap();
+        assert!((d.x.atan2(-d.z) - PI / 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn cubemap_faces_look_down_the_axes() {
+        let axes = [
+            [1.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, -1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 0.0, -1.0],
+        ];
+        for (face, axis) in axes.into_iter().enumerate() {
+            let s = (face as f64 + 0.5) / 6.0;
+            assert_near(Projection::Cubemap.direction(s, 0.5, 6.0).unwrap(), axis);
+        }
+        assert!(Projection::Perspective.direction(0.5, 0.5, 1.0).is_none());
+    }
+}
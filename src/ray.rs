@@ -1,10 +1,15 @@
-use crate::vec3::{Point, Vec3};
+use crate::{
+    spectrum::Wavelengths,
+    vec3::{Point, Vec3},
+};
 use std::ops::{Add, Mul};
 
 #[derive(Debug, Copy, Clone)]
 pub struct Ray<T> {
     origin: Point<T>,
     dir: Vec3<T>,
+    /// Set when rendering spectrally.
+    wavelengths: Option<Wavelengths>,
 }
 
 impl<T> Ray<T>
@@ -12,7 +17,20 @@ where
     T: Copy + Add<Output = T> + Mul<Output = T>,
 {
     pub fn new(origin: Point<T>, dir: Vec3<T>) -> Self {
-        Self { origin, dir }
+        Self {
+            origin,
+            dir,
+            wavelengths: None,
+        }
+    }
+
+    pub fn with_wavelengths(mut self, wavelengths: Option<Wavelengths>) -> Self {
+        self.wavelengths = wavelengths;
+        self
+    }
+
+    pub fn wavelengths(&self) -> Option<Wavelengths> {
+        self.wavelengths
     }
 
     pub fn get_origin(&self) -> &Point<T> {
@@ -0,0 +1,571 @@
+use crate::{
+    checkpoint::{invalid, read_u64, write_u64},
+    utils::{Vec3, PI},
+};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+/// Dimensions the camera takes before the first bounce: the pixel offset,
+/// the lens and the wavelengths of a spectral path.
+pub const CAMERA_DIMENSIONS: u32 = 3;
+/// Dimensions each bounce reserves for the material to pick a direction.
+pub const SCATTER_DIMENSIONS: u32 = 6;
+/// Dimensions each bounce reserves for a light sample, after the scatter
+/// ones.
+pub const LIGHT_DIMENSIONS: u32 = 2;
+pub const BOUNCE_DIMENSIONS: u32 = SCATTER_DIMENSIONS + LIGHT_DIMENSIONS;
+
+/// Source of sample values for one pixel sample at a time.
+///
+/// After `start_pixel_sample` every call to `get_1d`/`get_2d` consumes the
+/// next dimension. The camera takes the first `CAMERA_DIMENSIONS`, then
+/// every bounce gets `BOUNCE_DIMENSIONS` of its own, whatever the materials
+/// before it used: integrators call `start_bounce` and `start_light_sample`,
+/// which skip over whatever the previous step left unused. That way the
+/// same decision lands on the same dimension across a pixel's samples and
+/// sees a well distributed sequence. Only a material that needs more than
+/// its budget, like the random walk through a `Layered` coating, pushes the
+/// rest of its path out of step.
+pub trait Sampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32);
+
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> (f64, f64);
+
+    /// Skips ahead to `dimension`, unless already past it.
+    fn pad_to(&mut self, dimension: u32);
+
+    /// Moves to the dimensions of bounce `depth`, counting from 0.
+    fn start_bounce(&mut self, depth: u32) {
+        self.pad_to(CAMERA_DIMENSIONS + depth * BOUNCE_DIMENSIONS);
+    }
+
+    /// Moves to the light sample dimensions of bounce `depth`.
+    fn start_light_sample(&mut self, depth: u32) {
+        self.pad_to(CAMERA_DIMENSIONS + depth * BOUNCE_DIMENSIONS + SCATTER_DIMENSIONS);
+    }
+}
+
+impl fmt::Debug for dyn Sampler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Sampler")
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SamplerKind {
+    /// Uniform random numbers, no stratification at all.
+    Independent,
+    /// Jittered grid for every dimension, decorrelated by permuting strata.
+    Stratified,
+    /// Randomly shifted Halton sequence.
+    Halton,
+    /// Owen scrambled Sobol points, padded two dimensions at a time.
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn build(self, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+
+    /// Whether the values a sample gets depend on how many samples per
+    /// pixel there are, so a render can't take more later on.
+    pub fn depends_on_count(self) -> bool {
+        self == SamplerKind::Stratified
+    }
+
+    pub fn write_state(self, out: &mut impl Write) -> io::Result<()> {
+        write_u64(out, self as u64)
+    }
+
+    pub fn read_state(input: &mut impl Read) -> io::Result<SamplerKind> {
+        match read_u64(input)? {
+            0 => Ok(SamplerKind::Independent),
+            1 => Ok(SamplerKind::Stratified),
+            2 => Ok(SamplerKind::Halton),
+            3 => Ok(SamplerKind::Sobol),
+            _ => Err(invalid("unknown sampler")),
+        }
+    }
+}
+
+/// 2^-32, to turn 32 bits of a sample into [0, 1).
+const INV_2_32: f64 = 1.0 / 4294967296.0;
+
+/// Largest f64 below one, samples are clamped to it so they stay in [0, 1).
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+#[inline]
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    v
+}
+
+#[inline]
+fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0x243f6a8885a308d3, |h, &v| mix_bits(h ^ mix_bits(v)))
+}
+
+#[inline]
+fn pixel_key(pixel: (u32, u32)) -> u64 {
+    ((pixel.0 as u64) << 32) | pixel.1 as u64
+}
+
+/// Kensler's hashed permutation: the `i`th element of a random permutation
+/// of `0..l` selected by `p`, without storing the permutation.
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    i.wrapping_add(p) % l
+}
+
+/// A uniform value in [0, 1) derived from a hash.
+#[inline]
+fn hash_float(values: &[u64]) -> f64 {
+    (hash(values) >> 32) as f64 * INV_2_32
+}
+
+#[derive(Debug)]
+pub struct IndependentSampler {
+    seed: u64,
+    key: u64,
+    dimension: u64,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        Self {
+            seed,
+            key: 0,
+            dimension: 0,
+        }
+    }
+
+    fn next(&self, n: u64) -> f64 {
+        let bits = hash(&[self.key, self.dimension, n]);
+        (bits >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.key = hash(&[pixel_key(pixel), index as u64, self.seed]);
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let value = self.next(0);
+        self.dimension += 1;
+        value
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let value = (self.next(0), self.next(1));
+        self.dimension += 1;
+        value
+    }
+
+    fn pad_to(&mut self, dimension: u32) {
+        self.dimension = self.dimension.max(dimension as u64);
+    }
+}
+
+#[derive(Debug)]
+pub struct StratifiedSampler {
+    x_strata: u32,
+    y_strata: u32,
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl StratifiedSampler {
+    /// Splits `samples_per_pixel` into the squarest grid it divides evenly.
+    pub fn new(samples_per_pixel: u32, seed: u64) -> StratifiedSampler {
+        let samples_per_pixel = samples_per_pixel.max(1);
+        let x_strata = (1..=(samples_per_pixel as f64).sqrt() as u32)
+            .rev()
+            .find(|&x| samples_per_pixel.is_multiple_of(x))
+            .unwrap_or(1);
+        Self {
+            x_strata,
+            y_strata: samples_per_pixel / x_strata,
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn count(&self) -> u32 {
+        self.x_strata * self.y_strata
+    }
+
+    /// Which stratum this sample covers in the current dimension. Samples past
+    /// the grid size start another round with a fresh permutation.
+    fn stratum(&self) -> u32 {
+        let round = (self.index / self.count()) as u64;
+        let key = hash(&[self.pixel, self.dimension, round, self.seed]) as u32;
+        permutation_element(self.index % self.count(), self.count(), key)
+    }
+
+    fn jitter(&self, n: u64) -> f64 {
+        hash_float(&[self.pixel, self.dimension, self.index as u64, self.seed, n])
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.pixel = pixel_key(pixel);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let stratum = self.stratum();
+        let value = (stratum as f64 + self.jitter(0)) / self.count() as f64;
+        self.dimension += 1;
+        value.min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let stratum = self.stratum();
+        let (x, y) = (stratum % self.x_strata, stratum / self.x_strata);
+        let value = (
+            ((x as f64 + self.jitter(0)) / self.x_strata as f64).min(ONE_MINUS_EPSILON),
+            ((y as f64 + self.jitter(1)) / self.y_strata as f64).min(ONE_MINUS_EPSILON),
+        );
+        self.dimension += 1;
+        value
+    }
+
+    fn pad_to(&mut self, dimension: u32) {
+        self.dimension = self.dimension.max(dimension as u64);
+    }
+}
+
+const fn primes<const N: usize>() -> [u64; N] {
+    let mut primes = [0; N];
+    let mut found = 0;
+    let mut candidate = 2;
+    while found < N {
+        let mut divisor = 2;
+        let mut is_prime = true;
+        while divisor * divisor <= candidate {
+            if candidate % divisor == 0 {
+                is_prime = false;
+                break;
+            }
+            divisor += 1;
+        }
+        if is_prime {
+            primes[found] = candidate;
+            found += 1;
+        }
+        candidate += 1;
+    }
+    primes
+}
+
+/// Bases for the Halton dimensions, past these the sampler falls back to
+/// uniform random values.
+const PRIMES: [u64; 256] = primes();
+
+#[derive(Debug)]
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        Self {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn radical_inverse(base: u64, mut a: u64) -> f64 {
+        let inv_base = 1.0 / base as f64;
+        let mut inv_base_m = 1.0;
+        let mut reversed = 0;
+        while a > 0 {
+            let next = a / base;
+            reversed = reversed * base + (a - next * base);
+            inv_base_m *= inv_base;
+            a = next;
+        }
+        (reversed as f64 * inv_base_m).min(ONE_MINUS_EPSILON)
+    }
+
+    /// Every call takes two bases so a 1D and a 2D sample cover the same
+    /// number of dimensions.
+    fn value(&self, base_index: usize) -> f64 {
+        let Some(&base) = PRIMES.get(base_index) else {
+            return hash_float(&[self.pixel, base_index as u64, self.index as u64, self.seed]);
+        };
+
+        // Cranley-Patterson rotation so neighbouring pixels don't share points
+        let shift = hash_float(&[self.pixel, base_index as u64, self.seed]);
+        let value = Self::radical_inverse(base, self.index as u64) + shift;
+        (value - value.floor()).min(ONE_MINUS_EPSILON)
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.pixel = pixel_key(pixel);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let value = self.value(2 * self.dimension);
+        self.dimension += 1;
+        value
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let value = (
+            self.value(2 * self.dimension),
+            self.value(2 * self.dimension + 1),
+        );
+        self.dimension += 1;
+        value
+    }
+
+    fn pad_to(&mut self, dimension: u32) {
+        self.dimension = self.dimension.max(dimension as usize);
+    }
+}
+
+/// Generator matrix for the second Sobol dimension, the first one is just
+/// the bit reversed index.
+const SOBOL_1: [u32; 32] = {
+    let mut v = [0; 32];
+    v[0] = 1 << 31;
+    let mut i = 1;
+    while i < 32 {
+        v[i] = v[i - 1] ^ (v[i - 1] >> 1);
+        i += 1;
+    }
+    v
+};
+
+/// Owen scrambled Sobol following Burley's "Practical Hash-based Owen
+/// Scrambling": every pair of dimensions uses its own scramble and its own
+/// shuffle of the sample index.
+#[derive(Debug)]
+pub struct SobolSampler {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        Self {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn sobol(index: u32, dimension: usize) -> u32 {
+        if dimension == 0 {
+            return index.reverse_bits();
+        }
+        let mut value = 0;
+        let mut index = index;
+        let mut bit = 0;
+        while index != 0 {
+            if index & 1 != 0 {
+                value ^= SOBOL_1[bit];
+            }
+            index >>= 1;
+            bit += 1;
+        }
+        value
+    }
+
+    fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+        x = x.wrapping_add(seed);
+        x ^= x.wrapping_mul(0x6c50b47c);
+        x ^= x.wrapping_mul(0xb82f1e52);
+        x ^= x.wrapping_mul(0xc7afe638);
+        x ^= x.wrapping_mul(0x8d22f6e6);
+        x
+    }
+
+    fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+        Self::laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+    }
+
+    fn next(&mut self) -> (f64, f64) {
+        let key = hash(&[self.pixel, self.dimension, self.seed]);
+        self.dimension += 1;
+
+        let index = Self::nested_uniform_scramble(self.index, key as u32);
+        let x = Self::nested_uniform_scramble(Self::sobol(index, 0), (key >> 32) as u32);
+        let y = Self::nested_uniform_scramble(Self::sobol(index, 1), mix_bits(key) as u32);
+        (
+            (x as f64 * INV_2_32).min(ONE_MINUS_EPSILON),
+            (y as f64 * INV_2_32).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.pixel = pixel_key(pixel);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.next().0
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        self.next()
+    }
+
+    fn pad_to(&mut self, dimension: u32) {
+        self.dimension = self.dimension.max(dimension as u64);
+    }
+}
+
+/// A fresh 2D sample made from `u`, different for every `attempt`, for the
+/// odd decision that has to try again after a rejection.
+pub fn rehash_2d(u: (f64, f64), attempt: u32) -> (f64, f64) {
+    let key = [u.0.to_bits(), u.1.to_bits(), attempt as u64];
+    (
+        hash_float(&[key[0], key[1], key[2], 0]),
+        hash_float(&[key[0], key[1], key[2], 1]),
+    )
+}
+
+/// Maps a 2D sample to the unit disk, keeping strata compact (Shirley-Chiu
+/// concentric mapping) unlike rejection sampling.
+pub fn sample_unit_disk((u1, u2): (f64, f64)) -> Vec3<f64> {
+    let (a, b) = (2.0 * u1 - 1.0, 2.0 * u2 - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return Vec3::splat(0.0);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
+
+/// Uniformly distributed direction on the unit sphere.
+pub fn sample_unit_vector((u1, u2): (f64, f64)) -> Vec3<f64> {
+    let z = 1.0 - 2.0 * u1;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Uniformly distributed point inside the unit sphere.
+pub fn sample_in_unit_sphere(u: (f64, f64), u_radius: f64) -> Vec3<f64> {
+    sample_unit_vector(u) * u_radius.cbrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    #[test]
+    fn bounces_start_on_the_same_dimension() {
+        for kind in KINDS {
+            let mut short = kind.build(16, 7);
+            let mut long = kind.build(16, 7);
+            for sampler in [&mut short, &mut long] {
+                sampler.start_pixel_sample((3, 5), 2);
+                sampler.get_2d();
+                sampler.start_bounce(0);
+            }
+            short.get_1d();
+            long.get_2d();
+            long.get_1d();
+            long.get_1d();
+            short.start_bounce(1);
+            long.start_bounce(1);
+            assert_eq!(short.get_2d(), long.get_2d(), "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn padding_never_goes_back() {
+        for kind in KINDS {
+            let mut sampler = kind.build(16, 7);
+            sampler.start_pixel_sample((0, 0), 0);
+            sampler.start_light_sample(0);
+            let light = sampler.get_2d();
+            sampler.start_pixel_sample((0, 0), 0);
+            for _ in 0..CAMERA_DIMENSIONS + BOUNCE_DIMENSIONS {
+                sampler.get_1d();
+            }
+            sampler.start_light_sample(0);
+            assert_ne!(sampler.get_2d(), light, "{kind:?}");
+        }
+    }
+}
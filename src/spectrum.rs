@@ -0,0 +1,285 @@
+use crate::utils::{Color, Vec3};
+use std::sync::OnceLock;
+
+/// The visible range sampled, in nanometres.
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 720.0;
+
+/// The wavelengths, in nanometres, one path carries: a hero picked at
+/// random and two more spread evenly across the visible range from it, after
+/// Wilkie et al., "Hero Wavelength Spectral Sampling" (2014). Spectral
+/// values along the path go in a `Vec3`, one per wavelength.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Wavelengths {
+    lambda: [f64; 3],
+    /// Only the hero is left, the others having split off at something
+    /// dispersive.
+    single: bool,
+}
+
+impl Wavelengths {
+    pub fn sample(u: f64) -> Wavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let lambda = std::array::from_fn(|i| {
+            let offset = (u + i as f64 / 3.0).fract();
+            LAMBDA_MIN + offset * range
+        });
+        Self {
+            lambda,
+            single: false,
+        }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    /// Drops all but the hero, for when the wavelengths would go different
+    /// ways.
+    pub fn terminate_secondary(mut self) -> Self {
+        self.single = true;
+        self
+    }
+
+    /// A reflectance given in linear RGB, at each wavelength.
+    pub fn upsample(&self, rgb: Color<f64>) -> Vec3<f64> {
+        let [a, b, c] = self.lambda.map(|lambda| rgb_to_spectrum(rgb, lambda));
+        Vec3::new(a, b, c)
+    }
+
+    /// Linear sRGB from values at each wavelength, white balanced so a flat
+    /// spectrum of 1 comes out white.
+    pub fn to_rgb(&self, values: Vec3<f64>) -> Color<f64> {
+        let values = values.to_array();
+        let count = if self.single { 1 } else { 3 };
+        let mut xyz = Vec3::splat(0.0);
+        for (&lambda, &value) in self.lambda.iter().zip(&values).take(count) {
+            xyz += cie_xyz(lambda) * value;
+        }
+        // each wavelength is picked uniformly over the range
+        xyz *= (LAMBDA_MAX - LAMBDA_MIN) / count as f64;
+        let (rgb, white) = (xyz_to_linear_srgb(xyz), white());
+        Color::new(rgb.x / white.x, rgb.y / white.y, rgb.z / white.z)
+    }
+}
+
+/// Index of refraction as a function of wavelength.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Ior {
+    Constant(f64),
+    /// `a + b / λ²`, with λ in micrometres.
+    Cauchy {
+        a: f64,
+        b: f64,
+    },
+    /// `n² = 1 + Σ b λ² / (λ² - c)`, with λ in micrometres.
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3],
+    },
+}
+
+impl Ior {
+    /// Schott N-BK7, the usual crown glass for lenses and prisms.
+    pub fn bk7() -> Ior {
+        Ior::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+
+    /// Schott SF11, a dense flint that spreads light about twice as much.
+    pub fn dense_flint() -> Ior {
+        Ior::Sellmeier {
+            b: [1.73759695, 0.313747346, 1.89878101],
+            c: [0.013188707, 0.0623068142, 155.23629],
+        }
+    }
+
+    pub fn diamond() -> Ior {
+        Ior::Sellmeier {
+            b: [0.3306, 4.3356, 0.0],
+            c: [0.1750 * 0.1750, 0.1060 * 0.1060, 0.0],
+        }
+    }
+
+    pub fn water() -> Ior {
+        Ior::Cauchy {
+            a: 1.3236,
+            b: 0.00322,
+        }
+    }
+
+    /// The index at `wavelength` in nanometres.
+    pub fn at(&self, wavelength: f64) -> f64 {
+        let micrometres = wavelength / 1000.0;
+        let l2 = micrometres * micrometres;
+        match *self {
+            Ior::Constant(n) => n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => {
+                let sum: f64 = b.iter().zip(&c).map(|(b, c)| b * l2 / (l2 - c)).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+
+    /// The index at the sodium D line, what tables quote as just the index.
+    pub fn nd(&self) -> f64 {
+        self.at(589.3)
+    }
+}
+
+/// The CIE 1931 2° colour matching functions, by the multi-lobe Gaussian
+/// fit of Wyman et al., "Simple Analytic Approximations to the CIE XYZ
+/// Color Matching Functions" (2013).
+pub fn cie_xyz(wavelength: f64) -> Vec3<f64> {
+    let g = |mu: f64, below: f64, above: f64| {
+        let sigma = if wavelength < mu { below } else { above };
+        let t = (wavelength - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+pub fn xyz_to_linear_srgb(xyz: Vec3<f64>) -> Color<f64> {
+    Color::new(
+        3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z,
+        -0.9692660 * xyz.x + 1.8760108 * xyz.y + 0.0415560 * xyz.z,
+        0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z,
+    )
+}
+
+fn integrate_xyz(spectrum: impl Fn(f64) -> f64, steps: usize) -> Vec3<f64> {
+    let step = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+    let mut xyz = Vec3::splat(0.0);
+    for i in 0..steps {
+        let wavelength = LAMBDA_MIN + (i as f64 + 0.5) * step;
+        xyz += cie_xyz(wavelength) * (spectrum(wavelength) * step);
+    }
+    xyz
+}
+
+/// What a flat spectrum of 1 comes out as before white balancing.
+fn white() -> Color<f64> {
+    static WHITE: OnceLock<Color<f64>> = OnceLock::new();
+    *WHITE.get_or_init(|| xyz_to_linear_srgb(integrate_xyz(|_| 1.0, 1000)))
+}
+
+/// Linear sRGB for a whole spectrum, white balanced like `Wavelengths`.
+/// Coarse, but plenty for spectra as smooth as the colour matching
+/// functions.
+pub fn spectrum_to_rgb(spectrum: impl Fn(f64) -> f64) -> Color<f64> {
+    const STEPS: usize = 32;
+    static WHITE: OnceLock<Color<f64>> = OnceLock::new();
+    let white = *WHITE.get_or_init(|| xyz_to_linear_srgb(integrate_xyz(|_| 1.0, STEPS)));
+    let rgb = xyz_to_linear_srgb(integrate_xyz(spectrum, STEPS));
+    Color::new(rgb.x / white.x, rgb.y / white.y, rgb.z / white.z)
+}
+
+// Smits, "An RGB-to-Spectrum Conversion for Reflectances" (1999): smooth
+// spectra for white and each primary and secondary, in ten even bins over
+// the visible range
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// Smits' spectrum for `rgb` at `wavelength`: as much white as the smallest
+/// component, then the secondary and primary making up the rest.
+pub fn rgb_to_spectrum(rgb: Color<f64>, wavelength: f64) -> f64 {
+    let bin = ((wavelength - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 10.0).clamp(0.0, 9.0);
+    let bin = bin as usize;
+    let (r, g, b) = (rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0));
+    let spectrum = if r <= g && r <= b {
+        let rest = if g <= b {
+            (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+        } else {
+            (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+        };
+        r * SMITS_WHITE[bin] + rest
+    } else if g <= r && g <= b {
+        let rest = if r <= b {
+            (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+        } else {
+            (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+        };
+        g * SMITS_WHITE[bin] + rest
+    } else {
+        let rest = if r <= g {
+            (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+        } else {
+            (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+        };
+        b * SMITS_WHITE[bin] + rest
+    };
+    spectrum.max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn white_upsamples_flat_and_comes_back_white() {
+        let white = Color::splat(1.0);
+        let count = 1000;
+        let mut sum = Color::splat(0.0);
+        for i in 0..count {
+            let wavelengths = Wavelengths::sample((i as f64 + 0.5) / count as f64);
+            let spectrum = wavelengths.upsample(white);
+            for value in spectrum.to_array() {
+                assert!((value - 1.0).abs() < 1e-3, "{value}");
+            }
+            sum += wavelengths.to_rgb(spectrum) / count as f64;
+        }
+        for channel in sum.to_array() {
+            assert!((channel - 1.0).abs() < 1e-2, "{channel}");
+        }
+    }
+
+    #[test]
+    fn bk7_has_its_catalogue_index() {
+        assert!((Ior::bk7().nd() - 1.5168).abs() < 1e-4);
+        // and bends blue more than red
+        assert!(Ior::bk7().at(450.0) > Ior::bk7().at(650.0));
+    }
+
+    #[test]
+    fn colour_matching_functions_peak_where_they_should() {
+        let peak = |channel: usize| {
+            (380..=720)
+                .map(|w| (w, cie_xyz(w as f64).to_array()[channel]))
+                .fold(
+                    (0, f64::MIN),
+                    |best, next| if next.1 > best.1 { next } else { best },
+                )
+        };
+        let (x, y, z) = (peak(0), peak(1), peak(2));
+        assert!((595..=605).contains(&x.0), "{x:?}");
+        assert!((550..=560).contains(&y.0), "{y:?}");
+        assert!((1.0 - y.1).abs() < 1e-2, "{y:?}");
+        assert!((440..=450).contains(&z.0), "{z:?}");
+    }
+}
@@ -1,7 +1,8 @@
 use crate::{
     hit::{HitRecord, Hittable},
     material::Material,
-    utils::{Float, Interval, Point, Ray, Rc, Vec3},
+    sampler::{sample_unit_vector, Sampler},
+    utils::{Float, Interval, Onb, Point, Ray, Rc, Vec3, PI},
 };
 
 #[derive(Debug, Clone)]
@@ -25,6 +26,19 @@ impl<T: Float> Sphere<T> {
     }
 }
 
+impl<T> Sphere<T>
+where
+    T: Float,
+{
+    /// Longitude/latitude of a point on the unit sphere, both in [0, 1].
+    fn uv(p: &Point<T>) -> (T, T) {
+        let pi = T::from(PI).unwrap();
+        let theta = (-p.y).acos();
+        let phi = (-p.z).atan2(p.x) + pi;
+        (phi / (pi + pi), theta / pi)
+    }
+}
+
 impl<T> Hittable<T> for Sphere<T>
 where
     T: Copy + Float,
@@ -48,12 +62,83 @@ where
         }
 
         let point = ray.at(root);
+        let outward_normal = (point - self.center) / self.radius;
         Some(HitRecord::new(
             point,
-            (point - self.center) / self.radius,
+            outward_normal,
             root,
+            Self::uv(&outward_normal),
             ray,
             self.mat.clone(),
         ))
     }
+    fn pdf_value(&self, origin: &Point<T>, direction: &Vec3<T>) -> T {
+        let ray = Ray::new(*origin, *direction);
+        if self
+            .hit(&ray, Interval::new(T::from(0.001).unwrap(), T::infinity()))
+            .is_none()
+        {
+            return T::zero();
+        }
+
+        // from inside the whole sphere of directions leads to it
+        let dist_squared = (self.center - *origin).length_squared();
+        if dist_squared <= self.radius * self.radius {
+            return T::one() / T::from(4.0 * PI).unwrap();
+        }
+        let cos_theta_max = (T::one() - self.radius * self.radius / dist_squared).sqrt();
+        let solid_angle = T::from(2.0 * PI).unwrap() * (T::one() - cos_theta_max);
+        T::one() / solid_angle
+    }
+
+    fn material(&self) -> Option<Rc<dyn Material<T>>> {
+        Some(self.mat.clone())
+    }
+
+    fn random(&self, origin: &Point<T>, sampler: &mut dyn Sampler) -> Vec3<T> {
+        let direction = self.center - *origin;
+        let dist_squared = direction.length_squared();
+        let (r1, r2) = sampler.get_2d();
+        if dist_squared <= self.radius * self.radius {
+            return sample_unit_vector((r1, r2)).map(|c| T::from(c).unwrap());
+        }
+        let uvw = Onb::new(&direction);
+
+        // uniform over the cone of directions subtended by the sphere
+        let (r1, r2) = (T::from(r1).unwrap(), T::from(r2).unwrap());
+        let cos_theta_max = (T::one() - self.radius * self.radius / dist_squared).sqrt();
+        let z = T::one() + r2 * (cos_theta_max - T::one());
+        let phi = T::from(2.0 * PI).unwrap() * r1;
+        let sin_theta = (T::one() - z * z).sqrt();
+
+        uvw.transform(Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lambertian, utils::Color};
+
+    fn unit_sphere() -> Sphere<f64> {
+        let material = Rc::new(Lambertian::new(Color::splat(0.5)));
+        Sphere::new(Point::splat(0.0), 1.0, material)
+    }
+
+    #[test]
+    fn pdf_is_uniform_from_inside() {
+        let sphere = unit_sphere();
+        let pdf = sphere.pdf_value(&Point::new(0.2, 0.0, 0.0), &Vec3::new(0.0, 1.0, 0.0));
+        assert!((pdf - 1.0 / (4.0 * PI)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn pdf_matches_the_cone_from_outside() {
+        let sphere = unit_sphere();
+        let origin = Point::new(0.0, 0.0, 2.0);
+        let pdf = sphere.pdf_value(&origin, &Vec3::new(0.0, 0.0, -1.0));
+        let cos_theta_max = (1.0 - 1.0 / 4.0_f64).sqrt();
+        assert!((pdf - 1.0 / (2.0 * PI * (1.0 - cos_theta_max))).abs() < 1e-12);
+        assert_eq!(sphere.pdf_value(&origin, &Vec3::new(0.0, 0.0, 1.0)), 0.0);
+    }
 }
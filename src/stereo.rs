@@ -0,0 +1,114 @@
+use crate::utils::Vec3;
+
+/// Renders a view for each eye into one image. With an equirectangular
+/// projection this gives an omni-directional stereo (ODS) panorama, where
+/// the eyes circle the camera position as the view turns.
+#[derive(Debug, Copy, Clone)]
+pub struct Stereo {
+    /// Distance between the eyes, in world units.
+    pub interocular: f64,
+    /// Distance in front of the camera where the eyes' views line up, so
+    /// objects there appear at the depth of the screen. Infinity keeps the
+    /// eyes parallel.
+    pub convergence: f64,
+    pub layout: StereoLayout,
+}
+
+/// Where each eye's view goes in the image.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum StereoLayout {
+    /// Left eye in the left half.
+    #[default]
+    SideBySide,
+    /// Left eye in the top half, the usual layout for ODS.
+    TopBottom,
+}
+
+impl Stereo {
+    pub fn new(interocular: f64, convergence: f64, layout: StereoLayout) -> Stereo {
+        Self {
+            interocular,
+            convergence,
+            layout,
+        }
+    }
+
+    /// Size of a single eye's view in a `width` by `height` image.
+    pub fn eye_size(&self, width: f64, height: f64) -> (f64, f64) {
+        match self.layout {
+            StereoLayout::SideBySide => (width / 2.0, height),
+            StereoLayout::TopBottom => (width, height / 2.0),
+        }
+    }
+
+    /// Which eye raster position `(x, y)` belongs to, -1 for the left and 1
+    /// for the right, and where it is within that eye's view.
+    pub fn eye(&self, x: f64, y: f64, width: f64, height: f64) -> (f64, f64, f64) {
+        let (eye_width, eye_height) = self.eye_size(width, height);
+        match self.layout {
+            StereoLayout::SideBySide if x < eye_width => (-1.0, x, y),
+            StereoLayout::SideBySide => (1.0, x - eye_width, y),
+            StereoLayout::TopBottom if y < eye_height => (-1.0, x, y),
+            StereoLayout::TopBottom => (1.0, x, y - eye_height),
+        }
+    }
+
+    /// Where `eye` (-1 left, 1 right) sits from the camera, `right` being
+    /// the camera's unit right vector.
+    pub fn eye_offset(&self, eye: f64, right: Vec3<f64>) -> Vec3<f64> {
+        right * (eye * self.interocular / 2.0)
+    }
+
+    /// Where `eye` sits for an ODS ray along `direction`: on the circle the
+    /// eyes trace as the head turns about `up`, at right angles to the
+    /// direction. `None` looking straight up or down, where any way round
+    /// the circle is as good.
+    pub fn ods_offset(&self, eye: f64, direction: Vec3<f64>, up: Vec3<f64>) -> Option<Vec3<f64>> {
+        let right = direction.cross(&up);
+        if right.length_squared() <= 0.0 {
+            return None;
+        }
+        Some(self.eye_offset(eye, right.unit_vector()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eyes_sit_either_side_of_the_centre() {
+        let stereo = Stereo::new(0.064, f64::INFINITY, StereoLayout::SideBySide);
+        let right = Vec3::new(0.6, 0.0, -0.8);
+        let (left_eye, right_eye) = (
+            stereo.eye_offset(-1.0, right),
+            stereo.eye_offset(1.0, right),
+        );
+        assert!((left_eye + right * 0.032).length() < 1e-12);
+        assert!((right_eye - right * 0.032).length() < 1e-12);
+        assert_eq!(stereo.eye_offset(0.0, right).length(), 0.0);
+    }
+
+    #[test]
+    fn ods_eyes_stay_on_the_circle_looking_along_it() {
+        let stereo = Stereo::new(0.064, f64::INFINITY, StereoLayout::TopBottom);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        for i in 0..16 {
+            let phi = i as f64 / 16.0 * 2.0 * std::f64::consts::PI;
+            let direction = Vec3::new(phi.cos() * 0.8, 0.6, phi.sin() * 0.8);
+            for eye in [-1.0, 1.0] {
+                let offset = stereo.ods_offset(eye, direction, up).unwrap();
+                assert!((offset.length() - 0.032).abs() < 1e-12);
+                assert!(offset.dot(&up).abs() < 1e-12);
+                // the ray runs along the circle, not away from its centre
+                assert!(offset.dot(&direction).abs() < 1e-12);
+            }
+            let (left, right) = (
+                stereo.ods_offset(-1.0, direction, up).unwrap(),
+                stereo.ods_offset(1.0, direction, up).unwrap(),
+            );
+            assert!((left + right).length() < 1e-12);
+        }
+        assert!(stereo.ods_offset(1.0, up, up).is_none());
+    }
+}
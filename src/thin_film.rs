@@ -0,0 +1,254 @@
+use crate::{
+    spectrum::{rgb_to_spectrum, spectrum_to_rgb},
+    utils::{Color, PI},
+};
+use std::ops::{Add, Div, Mul, Sub};
+
+/// A thin transparent film on a surface, like soap, oil or an oxide layer.
+/// Light bouncing between its two sides interferes with itself, so how much
+/// gets reflected depends on wavelength, angle and thickness.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ThinFilm {
+    /// In nanometres.
+    pub thickness: f64,
+    pub ior: f64,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f64, ior: f64) -> ThinFilm {
+        Self { thickness, ior }
+    }
+
+    /// Soapy water, a few hundred nanometres for the colourful bubbles and
+    /// under 30 where it goes black just before popping.
+    pub fn soap(thickness: f64) -> ThinFilm {
+        Self::new(thickness, 1.33)
+    }
+
+    pub fn oil(thickness: f64) -> ThinFilm {
+        Self::new(thickness, 1.47)
+    }
+
+    /// Reflectance at `wavelength` in nanometres for unpolarized light
+    /// coming from a medium of index `outer` at `cos_theta`, onto the film
+    /// over a substrate of complex index `eta + i k`.
+    pub fn reflectance(
+        &self,
+        cos_theta: f64,
+        outer: f64,
+        eta: f64,
+        k: f64,
+        wavelength: f64,
+    ) -> f64 {
+        // everything glances off, and the formulas below go 0 / 0 there when
+        // the film matches the outside
+        if cos_theta <= 0.0 {
+            return 1.0;
+        }
+        let n1 = Complex::real(outer);
+        let n2 = Complex::real(self.ior);
+        let n3 = Complex::new(eta, k);
+
+        // Snell's law, complex past the critical angle and in metals
+        let cos1 = Complex::real(cos_theta.min(1.0));
+        let sin2 = Complex::real(outer * outer * (1.0 - cos1.re * cos1.re));
+        let cos_in = |n: Complex| (Complex::real(1.0) - sin2 / (n * n)).sqrt();
+        let (cos2, cos3) = (cos_in(n2), cos_in(n3));
+
+        // round trip through the film
+        let delta = Complex::real(4.0 * PI * self.thickness / wavelength) * n2 * cos2;
+        let phase = delta.exp_i();
+        let airy = |r12: Complex, r23: Complex| {
+            let one = Complex::real(1.0);
+            ((r12 + r23 * phase) / (one + r12 * r23 * phase)).norm_sqr()
+        };
+
+        let s = |ni: Complex, ci: Complex, nj: Complex, cj: Complex| {
+            (ni * ci - nj * cj) / (ni * ci + nj * cj)
+        };
+        let p = |ni: Complex, ci: Complex, nj: Complex, cj: Complex| {
+            (nj * ci - ni * cj) / (nj * ci + ni * cj)
+        };
+        let rs = airy(s(n1, cos1, n2, cos2), s(n2, cos2, n3, cos3));
+        let rp = airy(p(n1, cos1, n2, cos2), p(n2, cos2, n3, cos3));
+        ((rs + rp) / 2.0).clamp(0.0, 1.0)
+    }
+
+    /// The RGB approximation: reflectance over the visible range taken
+    /// through CIE XYZ, with the substrate's RGB index upsampled to a
+    /// spectrum.
+    pub fn reflectance_rgb(
+        &self,
+        cos_theta: f64,
+        outer: f64,
+        eta: Color<f64>,
+        k: Color<f64>,
+    ) -> Color<f64> {
+        spectrum_to_rgb(|wavelength| {
+            let eta = rgb_to_spectrum(eta, wavelength);
+            let k = rgb_to_spectrum(k, wavelength);
+            self.reflectance(cos_theta, outer, eta, k, wavelength)
+        })
+        // saturated enough to land a little outside the gamut
+        .map(|c| c.clamp(0.0, 1.0))
+    }
+
+    /// `reflectance_rgb` over the whole range of angles, for when the
+    /// indices on both sides won't change.
+    pub fn tabulate(&self, outer: f64, eta: Color<f64>, k: Color<f64>) -> ReflectanceTable {
+        let last = (TABLE_SIZE - 1) as f64;
+        ReflectanceTable {
+            values: std::array::from_fn(|i| self.reflectance_rgb(i as f64 / last, outer, eta, k)),
+        }
+    }
+}
+
+const TABLE_SIZE: usize = 64;
+
+/// `ThinFilm::reflectance_rgb` at evenly spaced cosines, since integrating
+/// over the spectrum every time light hits the film is slow.
+#[derive(Debug, Copy, Clone)]
+pub struct ReflectanceTable {
+    values: [Color<f64>; TABLE_SIZE],
+}
+
+impl ReflectanceTable {
+    /// Linearly interpolated between the two nearest cosines.
+    pub fn at(&self, cos_theta: f64) -> Color<f64> {
+        let x = cos_theta.clamp(0.0, 1.0) * (TABLE_SIZE - 1) as f64;
+        let i = (x as usize).min(TABLE_SIZE - 2);
+        let t = x - i as f64;
+        self.values[i] * (1.0 - t) + self.values[i + 1] * t
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Complex {
+        Self { re, im }
+    }
+
+    fn real(re: f64) -> Complex {
+        Self::new(re, 0.0)
+    }
+
+    fn norm_sqr(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    /// The principal root, which keeps evanescent waves decaying.
+    fn sqrt(self) -> Complex {
+        let r = self.norm_sqr().sqrt();
+        let re = ((r + self.re) / 2.0).max(0.0).sqrt();
+        let im = ((r - self.re) / 2.0).max(0.0).sqrt();
+        Self::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    /// `e^(i z)`.
+    fn exp_i(self) -> Complex {
+        let scale = (-self.im).exp();
+        Self::new(scale * self.re.cos(), scale * self.re.sin())
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+
+    fn div(self, other: Complex) -> Complex {
+        let denominator = other.norm_sqr();
+        Complex::new(
+            (self.re * other.re + self.im * other.im) / denominator,
+            (self.im * other.re - self.re * other.im) / denominator,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::microfacet::{fresnel_conductor, fresnel_dielectric};
+
+    const COSINES: [f64; 6] = [0.0, 0.1, 0.3, 0.5, 0.8, 1.0];
+
+    #[test]
+    fn no_thickness_is_just_the_substrate() {
+        let film = ThinFilm::soap(0.0);
+        for cos in COSINES {
+            for (outer, inner) in [(1.0, 1.5), (1.5, 1.0), (1.33, 2.4)] {
+                let expected = fresnel_dielectric(cos, inner / outer);
+                let r = film.reflectance(cos, outer, inner, 0.0, 550.0);
+                assert!((r - expected).abs() < 1e-9, "{cos} {outer} {inner}");
+            }
+
+            let (eta, k) = (0.143, 3.983);
+            let expected = fresnel_conductor(cos, Color::splat(eta), Color::splat(k)).x;
+            let r = film.reflectance(cos, 1.0, eta, k, 550.0);
+            assert!((r - expected).abs() < 1e-9, "{cos}");
+        }
+    }
+
+    #[test]
+    fn bare_soap_film_in_air_reflects_nothing() {
+        let film = ThinFilm::soap(0.0);
+        for cos in COSINES.into_iter().skip(1) {
+            assert!(film.reflectance(cos, 1.0, 1.0, 0.0, 550.0) < 1e-12);
+        }
+    }
+
+    #[test]
+    fn reflectance_stays_between_zero_and_one() {
+        for film in [ThinFilm::soap(350.0), ThinFilm::oil(800.0)] {
+            for cos in COSINES {
+                for wavelength in [400.0, 550.0, 700.0] {
+                    for (eta, k) in [(1.5, 0.0), (0.2, 3.0), (1.0, 0.0)] {
+                        let r = film.reflectance(cos, 1.0, eta, k, wavelength);
+                        assert!((0.0..=1.0).contains(&r), "{r}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn table_follows_the_full_integral() {
+        let film = ThinFilm::soap(400.0);
+        let (eta, k) = (Color::splat(1.5), Color::splat(0.0));
+        let table = film.tabulate(1.0, eta, k);
+        for cos in [0.05, 0.25, 0.62, 0.97] {
+            let error = (table.at(cos) - film.reflectance_rgb(cos, 1.0, eta, k)).length();
+            assert!(error < 0.02, "{cos} {error}");
+        }
+    }
+}
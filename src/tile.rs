@@ -0,0 +1,230 @@
+/// A rectangle of pixels, `x` and `y` being its top left corner.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Region {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The part of the region that lies inside a `width` by `height` image.
+    pub fn clamp(&self, width: usize, height: usize) -> Region {
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+        Self {
+            x,
+            y,
+            width: self.width.min(width - x),
+            height: self.height.min(height - y),
+        }
+    }
+
+    pub fn pixel_count(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (self.y..self.y + self.height)
+            .flat_map(move |j| (self.x..self.x + self.width).map(move |i| (i, j)))
+    }
+}
+
+/// What to write out when only a region of the image was rendered.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum RegionOutput {
+    /// Just the region, as an image of its own.
+    #[default]
+    Crop,
+    /// The whole frame, black everywhere outside the region.
+    FullFrame,
+}
+
+/// The order tiles are handed out in.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Left to right, top to bottom.
+    Scanline,
+    /// Outwards from the middle of the image, where the subject usually is.
+    #[default]
+    Spiral,
+    /// Along a Hilbert curve, which keeps consecutive tiles next to each
+    /// other.
+    Hilbert,
+}
+
+/// Splits the image into square tiles and decides what order to render
+/// them in.
+#[derive(Debug, Copy, Clone)]
+pub struct Tiling {
+    pub size: usize,
+    pub order: TileOrder,
+}
+
+impl Default for Tiling {
+    fn default() -> Self {
+        Self::new(32, TileOrder::default())
+    }
+}
+
+impl Tiling {
+    pub fn new(size: usize, order: TileOrder) -> Tiling {
+        Self {
+            size: size.max(1),
+            order,
+        }
+    }
+
+    /// Covers `region` with tiles, in render order. Tiles on the right and
+    /// bottom edges are cut short to fit.
+    pub fn tiles(&self, region: Region) -> Vec<Region> {
+        let columns = region.width.div_ceil(self.size);
+        let rows = region.height.div_ceil(self.size);
+        let grid = match self.order {
+            TileOrder::Scanline => (0..rows)
+                .flat_map(|row| (0..columns).map(move |column| (column, row)))
+                .collect(),
+            TileOrder::Spiral => spiral(columns, rows),
+            TileOrder::Hilbert => hilbert(columns, rows),
+        };
+
+        grid.into_iter()
+            .map(|(column, row)| {
+                let x = region.x + column * self.size;
+                let y = region.y + row * self.size;
+                Region::new(
+                    x,
+                    y,
+                    self.size.min(region.x + region.width - x),
+                    self.size.min(region.y + region.height - y),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Walks a square spiral around the middle cell, keeping the cells that fall
+/// inside the grid.
+fn spiral(columns: usize, rows: usize) -> Vec<(usize, usize)> {
+    let total = columns * rows;
+    let mut cells = Vec::with_capacity(total);
+    if total == 0 {
+        return cells;
+    }
+
+    let (mut x, mut y) = (((columns - 1) / 2) as isize, ((rows - 1) / 2) as isize);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut run = 1;
+    let mut turn = 0;
+
+    let visit = |x: isize, y: isize, cells: &mut Vec<(usize, usize)>| {
+        if (0..columns as isize).contains(&x) && (0..rows as isize).contains(&y) {
+            cells.push((x as usize, y as usize));
+        }
+    };
+
+    visit(x, y, &mut cells);
+    while cells.len() < total {
+        // two legs per run length: right, down, then left, up one longer
+        for _ in 0..2 {
+            let (dx, dy) = directions[turn % 4];
+            for _ in 0..run {
+                x += dx;
+                y += dy;
+                visit(x, y, &mut cells);
+            }
+            turn += 1;
+        }
+        run += 1;
+    }
+    cells
+}
+
+/// Orders the cells by their distance along a Hilbert curve over the
+/// smallest power of two square that holds the grid.
+fn hilbert(columns: usize, rows: usize) -> Vec<(usize, usize)> {
+    let side = columns.max(rows).next_power_of_two();
+    let mut cells: Vec<_> = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .collect();
+    cells.sort_by_key(|&(x, y)| hilbert_index(side, x, y));
+    cells
+}
+
+fn hilbert_index(side: usize, mut x: usize, mut y: usize) -> usize {
+    let mut index = 0;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = usize::from(x & s > 0);
+        let ry = usize::from(y & s > 0);
+        index += s * s * ((3 * rx) ^ ry);
+        // rotate the quadrant so the curve stays connected
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut tiles: Vec<Region>) -> Vec<(usize, usize, usize, usize)> {
+        tiles.sort_by_key(|t| (t.y, t.x));
+        tiles
+            .iter()
+            .map(|t| (t.x, t.y, t.width, t.height))
+            .collect()
+    }
+
+    #[test]
+    fn every_order_covers_the_same_tiles() {
+        let region = Region::new(3, 5, 70, 45);
+        let scanline = Tiling::new(16, TileOrder::Scanline).tiles(region);
+        assert_eq!(scanline.len(), 5 * 3);
+        assert_eq!(
+            scanline.iter().map(Region::pixel_count).sum::<usize>(),
+            70 * 45
+        );
+        // the last column and row are cut short
+        assert_eq!(scanline.last(), Some(&Region::new(67, 37, 6, 13)));
+
+        for order in [TileOrder::Spiral, TileOrder::Hilbert] {
+            let tiles = Tiling::new(16, order).tiles(region);
+            assert_eq!(sorted(tiles), sorted(scanline.clone()), "{order:?}");
+        }
+    }
+
+    #[test]
+    fn spiral_starts_in_the_middle() {
+        let cells = spiral(5, 3);
+        assert_eq!(cells[0], (2, 1));
+        assert_eq!(&cells[1..3], &[(3, 1), (3, 2)]);
+        assert_eq!(cells.len(), 15);
+    }
+
+    #[test]
+    fn hilbert_steps_to_a_neighbour() {
+        let cells = hilbert(8, 8);
+        assert_eq!(cells[0], (0, 0));
+        for pair in cells.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!(a.0.abs_diff(b.0) + a.1.abs_diff(b.1), 1);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use crate::utils::{luminance, Color};
+
+/// Compresses scene referred linear values into the [0, 1] display range.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneOperator {
+    /// No compression, anything above one just clips.
+    Clamp,
+    /// `L / (1 + L)` on luminance, never quite reaches white.
+    Reinhard,
+    /// Reinhard that maps luminance `white` (and above) to pure white.
+    ExtendedReinhard { white: f64 },
+    /// Stephen Hill's fit of the ACES reference and output transforms.
+    AcesFilmic,
+    /// Minimal AgX base look, desaturates highlights instead of skewing hue.
+    AgX,
+}
+
+/// Exposure (in stops) followed by a tone operator, producing linear values
+/// ready for the sRGB transfer function.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToneMap {
+    pub exposure: f64,
+    pub operator: ToneOperator,
+}
+
+impl Default for ToneMap {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            operator: ToneOperator::Clamp,
+        }
+    }
+}
+
+impl ToneMap {
+    pub fn new(exposure: f64, operator: ToneOperator) -> ToneMap {
+        Self { exposure, operator }
+    }
+
+    pub fn apply(&self, color: Color<f64>) -> Color<f64> {
+        let color = color * self.exposure.exp2();
+        let mapped = match self.operator {
+            ToneOperator::Clamp => color,
+            ToneOperator::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneOperator::ExtendedReinhard { white } => {
+                scale_luminance(color, |l| l * (1.0 + l / (white * white)) / (1.0 + l))
+            }
+            ToneOperator::AcesFilmic => aces_filmic(color),
+            ToneOperator::AgX => agx(color),
+        };
+        mapped.map(|c| c.clamp(0.0, 1.0))
+    }
+}
+
+fn scale_luminance(color: Color<f64>, curve: impl Fn(f64) -> f64) -> Color<f64> {
+    let l = luminance(color);
+    if l <= 0.0 {
+        return Color::splat(0.0);
+    }
+    color * (curve(l) / l)
+}
+
+/// Row major 3x3 matrix times a color.
+fn transform(m: &[[f64; 3]; 3], c: Color<f64>) -> Color<f64> {
+    Color::new(
+        m[0][0] * c.x + m[0][1] * c.y + m[0][2] * c.z,
+        m[1][0] * c.x + m[1][1] * c.y + m[1][2] * c.z,
+        m[2][0] * c.x + m[2][1] * c.y + m[2][2] * c.z,
+    )
+}
+
+fn aces_filmic(color: Color<f64>) -> Color<f64> {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    const INPUT: [[f64; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    const OUTPUT: [[f64; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+
+    let rrt_and_odt_fit = |v: f64| {
+        let a = v * (v + 0.0245786) - 0.000090537;
+        let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+        a / b
+    };
+    transform(&OUTPUT, transform(&INPUT, color).map(rrt_and_odt_fit))
+}
+
+fn agx(color: Color<f64>) -> Color<f64> {
+    const INSET: [[f64; 3]; 3] = [
+        [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+        [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+        [0.0423756549057051, 0.0784336, 0.879142973793104],
+    ];
+    const OUTSET: [[f64; 3]; 3] = [
+        [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+        [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+        [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+    ];
+    const MIN_EV: f64 = -12.47393;
+    const MAX_EV: f64 = 4.026069;
+
+    // polynomial fit of the AgX base contrast curve
+    let contrast = |x: f64| {
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    };
+
+    let encoded = transform(&INSET, color).map(|c| {
+        let ev = c.max(1e-10).log2().clamp(MIN_EV, MAX_EV);
+        contrast((ev - MIN_EV) / (MAX_EV - MIN_EV))
+    });
+    // the curve's output is display encoded with a 2.2 gamma, undo that so
+    // the result goes through the same sRGB encoding as everything else
+    transform(&OUTSET, encoded).map(|c| c.max(0.0).powf(2.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneOperator; 5] = [
+        ToneOperator::Clamp,
+        ToneOperator::Reinhard,
+        ToneOperator::ExtendedReinhard { white: 4.0 },
+        ToneOperator::AcesFilmic,
+        ToneOperator::AgX,
+    ];
+
+    #[test]
+    fn black_stays_black() {
+        for operator in OPERATORS {
+            let mapped = ToneMap::new(0.0, operator).apply(Color::splat(0.0));
+            assert!(
+                mapped.length() < 1e-3,
+                "{operator:?} {:?}",
+                mapped.to_array()
+            );
+        }
+    }
+
+    #[test]
+    fn brighter_never_maps_darker() {
+        for operator in OPERATORS {
+            let tone_map = ToneMap::new(0.0, operator);
+            let mut previous = Color::splat(0.0);
+            for i in 1..=400 {
+                let gray = Color::splat(i as f64 * 0.05);
+                let mapped = tone_map.apply(gray);
+                for (a, b) in previous.to_array().iter().zip(mapped.to_array()) {
+                    assert!(b >= a - 1e-12, "{operator:?} at {}", i as f64 * 0.05);
+                }
+                previous = mapped;
+            }
+        }
+    }
+
+    #[test]
+    fn operators_reach_their_white_points() {
+        let extended = ToneMap::new(0.0, ToneOperator::ExtendedReinhard { white: 4.0 });
+        let white = extended.apply(Color::splat(4.0));
+        assert!((white - Color::splat(1.0)).length() < 1e-12);
+        // plain Reinhard only gets there in the limit
+        let reinhard = ToneMap::new(0.0, ToneOperator::Reinhard);
+        assert!(reinhard.apply(Color::splat(1000.0)).x < 1.0);
+
+        let aces = ToneMap::new(0.0, ToneOperator::AcesFilmic);
+        for value in [0.1, 1.0, 10.0, 1000.0] {
+            let mapped = aces.apply(Color::new(value, value * 0.5, value * 2.0));
+            assert!(mapped.to_array().iter().all(|c| (0.0..=1.0).contains(c)));
+        }
+        assert!(aces.apply(Color::splat(1000.0)).x > 0.99);
+    }
+
+    #[test]
+    fn exposure_scales_before_the_curve() {
+        let color = Color::new(0.3, 0.6, 1.2);
+        for operator in OPERATORS {
+            let exposed = ToneMap::new(1.5, operator).apply(color);
+            let scaled = ToneMap::new(0.0, operator).apply(color * 1.5_f64.exp2());
+            assert!((exposed - scaled).length() < 1e-12, "{operator:?}");
+        }
+    }
+}
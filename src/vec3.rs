@@ -272,3 +272,37 @@ where
 }
 
 pub type Point<T> = Vec3<T>;
+
+/// Orthonormal basis with `w` along a given direction.
+#[derive(Debug, Copy, Clone)]
+pub struct Onb<T> {
+    pub u: Vec3<T>,
+    pub v: Vec3<T>,
+    pub w: Vec3<T>,
+}
+
+impl<T> Onb<T>
+where
+    T: Float,
+{
+    pub fn new(n: &Vec3<T>) -> Onb<T> {
+        let w = n.unit_vector();
+        let a = if w.x.abs() > T::from(0.9).unwrap() {
+            Vec3::new(T::zero(), T::one(), T::zero())
+        } else {
+            Vec3::new(T::one(), T::zero(), T::zero())
+        };
+        let v = w.cross(&a).unit_vector();
+        let u = w.cross(&v);
+        Self { u, v, w }
+    }
+
+    pub fn transform(&self, v: Vec3<T>) -> Vec3<T> {
+        self.u * v.x + self.v * v.y + self.w * v.z
+    }
+
+    /// The inverse of `transform`.
+    pub fn to_local(&self, v: Vec3<T>) -> Vec3<T> {
+        Vec3::new(v.dot(&self.u), v.dot(&self.v), v.dot(&self.w))
+    }
+}